@@ -0,0 +1,14 @@
+mod ops;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [a, op, b] = args.as_slice() else {
+        eprintln!("usage: calculator <a> <op> <b>");
+        std::process::exit(1);
+    };
+    let (a, b): (f64, f64) = (a.parse().unwrap(), b.parse().unwrap());
+    match ops::apply(a, op, b) {
+        Some(result) => println!("{}", result),
+        None => eprintln!("unknown operator {}", op),
+    }
+}
@@ -0,0 +1,11 @@
+/// Applies a binary operator. Division by zero yields infinity.
+pub fn apply(a: f64, op: &str, b: f64) -> Option<f64> {
+    match op {
+        "+" => Some(a + b),
+        "-" => Some(a - b),
+        "*" | "x" => Some(a * b),
+        "/" => Some(a / b),
+        "^" => Some(a.powf(b)),
+        _ => None,
+    }
+}
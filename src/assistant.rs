@@ -1,350 +1,791 @@
 use crate::{
-    constants::{ASSISTANT, CONTENT, ROLE, SYSTEM, SYSTEM_PROMPT, TOOL_CALLS, USER},
-    tools::{Tool, Toolchain},
+    backend::{self, ChatBackend, RetryPolicy, Timeouts},
+    config::ModelOptions,
+    events::{AssistantEvent, EventHandler, WrapUpReason},
+    ollama::{ChatMessage, ChatRequest, ChatResponse, OllamaClient, OllamaError, ToolCall},
+    prompts::{self, TemplateContext},
+    repetition::RepetitionTracker,
+    tool_parser,
+    tools::{PreparedCall, Tool, ToolError, ToolSet, Toolchain},
 };
-use reqwest;
-use serde_json::{Value, json};
-use std::{collections::HashMap, fmt::Display};
+use futures::future::{BoxFuture, join_all};
+use serde::Serialize;
+use serde_json::json;
+use std::{fmt::Display, sync::Arc, time::Instant};
+use tokio_util::sync::CancellationToken;
 
-pub type ProgressCallback = Box<dyn Fn(&str) + Send + Sync>;
+/// Called with the number of turns used when a question runs out of turns.
+/// Resolves to how many more turns to allow, 0 wraps up.
+pub type TurnLimitCallback = Box<dyn Fn(usize) -> BoxFuture<'static, usize> + Send + Sync>;
+
+/// Called before a tool that needs approval runs. Resolves to whether the
+/// call may run.
+pub type ApprovalCallback = Box<dyn Fn(&ToolCall) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// What it took to answer the last question
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TurnStats {
+    /// Requests to the model, including the final one when wrapping up
+    pub requests: usize,
+    pub tool_calls: usize,
+    /// Tool calls that were rejected or failed
+    pub tool_errors: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+const WRAP_UP_PROMPT: &str = "Stop calling tools now. \
+Answer the question with the information you have, and say what you could not find out.";
 
 #[derive(Debug)]
 pub enum AssistantError {
-    RequestError(reqwest::Error),
-    JsonError(serde_json::Error),
-    ToolError(String),
+    /// The server couldn't be reached, was overloaded or took too long,
+    /// also after retrying
+    BackendUnavailable(OllamaError),
+    ModelNotFound(String),
+    /// The conversation doesn't fit into the model's context window
+    ContextOverflow(String),
+    /// The server answered with something that can't be understood
+    InvalidResponse(OllamaError),
+    /// Any other error the server reported
+    RequestFailed(OllamaError),
+    /// A tool is broken, as opposed to a single call failing
+    ToolFailure {
+        tool: String,
+        message: String,
+    },
+    Cancelled,
+}
+
+impl AssistantError {
+    /// Errors that might go away when the request is made again
+    pub fn is_transient(&self) -> bool {
+        matches!(self, AssistantError::BackendUnavailable(_))
+    }
 }
 
 impl Display for AssistantError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AssistantError::RequestError(error) => write!(f, "{}", error),
-            AssistantError::JsonError(error) => write!(f, "{}", error),
-            AssistantError::ToolError(error) => write!(f, "{}", error),
+            AssistantError::BackendUnavailable(error) => {
+                write!(f, "Backend unavailable: {}", error)
+            }
+            AssistantError::ModelNotFound(model) => write!(
+                f,
+                "Model '{}' not found. Pull it with `ollama pull {}`",
+                model, model
+            ),
+            AssistantError::ContextOverflow(message) => write!(
+                f,
+                "The conversation doesn't fit into the model's context window ({}). \
+                Start a new session or raise num_ctx",
+                message
+            ),
+            AssistantError::InvalidResponse(error) => write!(f, "Invalid response: {}", error),
+            AssistantError::RequestFailed(error) => write!(f, "{}", error),
+            AssistantError::ToolFailure { tool, message } => {
+                write!(f, "Tool '{}' failed: {}", tool, message)
+            }
+            AssistantError::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
 
-impl From<reqwest::Error> for AssistantError {
-    fn from(err: reqwest::Error) -> Self {
-        AssistantError::RequestError(err)
-    }
-}
+impl std::error::Error for AssistantError {}
 
-impl From<serde_json::Error> for AssistantError {
-    fn from(err: serde_json::Error) -> Self {
-        AssistantError::JsonError(err)
+impl From<OllamaError> for AssistantError {
+    fn from(err: OllamaError) -> Self {
+        match err {
+            OllamaError::ModelNotFoundError(model) => AssistantError::ModelNotFound(model),
+            OllamaError::HttpError { ref message, .. }
+            | OllamaError::StreamedError(ref message)
+                if err.is_context_overflow() =>
+            {
+                AssistantError::ContextOverflow(message.clone())
+            }
+            _ if err.is_transient() => AssistantError::BackendUnavailable(err),
+            OllamaError::ReqwestError(_)
+            | OllamaError::StreamError(_)
+            | OllamaError::JsonError(_)
+            | OllamaError::MalformedResponseError(_) => AssistantError::InvalidResponse(err),
+            // Errors in the middle of a stream are failures of the request,
+            // sending it again would most likely fail the same way
+            OllamaError::HttpError { .. }
+            | OllamaError::StreamedError(_)
+            | OllamaError::ReplayError(_)
+            | OllamaError::IoError(_)
+            | OllamaError::TimeoutError { .. } => AssistantError::RequestFailed(err),
+        }
     }
 }
 
 pub struct Assistant {
     model: String,
-    client: reqwest::Client,
-    toolchain: Toolchain,
-    conversation: Vec<HashMap<String, Value>>,
-    progress_callback: Option<ProgressCallback>,
+    options: ModelOptions,
+    max_turns: usize,
+    backend: Box<dyn ChatBackend>,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    tools: ToolSet,
+    native_tools: bool,
+    /// Whether responses are constrained to JSON, see `prompts::asks_for_json`
+    json_format: bool,
+    conversation: Vec<ChatMessage>,
+    event_handlers: Vec<EventHandler>,
+    turn_limit_callback: Option<TurnLimitCallback>,
+    approval_callback: Option<ApprovalCallback>,
+    stats: TurnStats,
 }
 
 impl std::fmt::Debug for Assistant {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Assistant")
             .field("model", &self.model)
-            .field("toolchain", &self.toolchain)
+            .field("backend", &self.backend.describe())
+            .field("retry", &self.retry)
+            .field("timeouts", &self.timeouts)
+            .field("options", &self.options)
+            .field("max_turns", &self.max_turns)
+            .field("tools", &self.tools)
+            .field("native_tools", &self.native_tools)
+            .field("json_format", &self.json_format)
             .field("conversation_length", &self.conversation.len())
-            .field("has_progress_callback", &self.progress_callback.is_some())
+            .field("event_handlers", &self.event_handlers.len())
+            .field(
+                "has_turn_limit_callback",
+                &self.turn_limit_callback.is_some(),
+            )
+            .field("has_approval_callback", &self.approval_callback.is_some())
             .finish()
     }
 }
 
-impl Assistant {
-    pub fn new(model: String) -> Self {
-        let mut conversation = Vec::new();
-
-        // Add system message
-        conversation.push(HashMap::from([
-            (ROLE.into(), Value::String(SYSTEM.into())),
-            (CONTENT.into(), Value::String(SYSTEM_PROMPT.to_string())),
-        ]));
+/// Configures an `Assistant`. Everything but the model has a default: the
+/// built-in tools working in the current directory, the Ollama server on
+/// localhost and the built-in system prompt for the model.
+pub struct AssistantBuilder {
+    model: String,
+    options: ModelOptions,
+    max_turns: usize,
+    backend: Option<Box<dyn ChatBackend>>,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    toolchain: Toolchain,
+    tools: Vec<Arc<dyn Tool>>,
+    native_tools: bool,
+    system_prompt: Option<String>,
+    instructions: Option<String>,
+    event_handlers: Vec<EventHandler>,
+    turn_limit_callback: Option<TurnLimitCallback>,
+    approval_callback: Option<ApprovalCallback>,
+}
 
+impl AssistantBuilder {
+    pub fn new(model: impl Into<String>) -> Self {
         Self {
-            model,
-            client: reqwest::Client::new(),
+            model: model.into(),
+            options: ModelOptions::default(),
+            max_turns: 10,
+            backend: None,
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
             toolchain: Toolchain::default(),
-            conversation,
-            progress_callback: None,
+            tools: Vec::new(),
+            native_tools: true,
+            system_prompt: None,
+            instructions: None,
+            event_handlers: Vec::new(),
+            turn_limit_callback: None,
+            approval_callback: None,
         }
     }
 
-    pub fn with_progress_callback(mut self, callback: ProgressCallback) -> Self {
-        self.progress_callback = Some(callback);
+    pub fn with_backend(mut self, backend: Box<dyn ChatBackend>) -> Self {
+        self.backend = Some(backend);
         self
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn ask(&mut self, question: &str) -> Result<String, AssistantError> {
+    /// How often to retry requests that failed because the server was
+    /// unavailable
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_options(mut self, options: ModelOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// How many model requests a single question may take before the model
+    /// is asked to wrap up
+    pub fn with_max_turns(mut self, max_turns: usize) -> Self {
+        self.max_turns = max_turns;
+        self
+    }
+
+    /// The built-in tools, and the workspace they work in
+    pub fn with_toolchain(mut self, toolchain: Toolchain) -> Self {
+        self.toolchain = toolchain;
+        self
+    }
+
+    /// Adds a tool next to the built-in ones. A tool named like a built-in
+    /// one replaces it.
+    pub fn with_tool(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.push(Arc::new(tool));
+        self
+    }
+
+    /// Models without native tool support reject requests that include tool
+    /// definitions. For those, the tools are only described in the system
+    /// prompt and calls are parsed from the message content.
+    pub fn with_native_tools(mut self, native_tools: bool) -> Self {
+        self.native_tools = native_tools;
+        self
+    }
+
+    /// Replaces the built-in system prompt. Supports the same placeholders
+    /// as the built-in templates, e.g. {{workspace_root}} and {{tools}}.
+    pub fn with_system_prompt(mut self, template: impl Into<String>) -> Self {
+        self.system_prompt = Some(template.into());
+        self
+    }
+
+    /// Appends project or user provided instructions to the system message.
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    /// Subscribes to the events of every question. Can be called more than
+    /// once, e.g. to show progress and log events at the same time.
+    pub fn with_event_handler(mut self, handler: EventHandler) -> Self {
+        self.event_handlers.push(handler);
+        self
+    }
+
+    /// Lets the user grant more turns when a question runs out of them.
+    /// Without a callback, the model has to wrap up right away.
+    pub fn with_turn_limit_callback(mut self, callback: TurnLimitCallback) -> Self {
+        self.turn_limit_callback = Some(callback);
+        self
+    }
+
+    /// Lets the user decide about calls to tools that need approval.
+    /// Without a callback, those calls are denied.
+    pub fn with_approval_callback(mut self, callback: ApprovalCallback) -> Self {
+        self.approval_callback = Some(callback);
+        self
+    }
+
+    pub fn build(self) -> Assistant {
+        let mut tools = ToolSet::default();
+        for tool in self.toolchain.tools().into_iter().chain(self.tools) {
+            tools.add(tool);
+        }
+
+        let template = self
+            .system_prompt
+            .unwrap_or_else(|| prompts::template_for(&self.model, self.native_tools).to_string());
+        let context =
+            TemplateContext::new(&self.model, self.toolchain.root(), &tools.definitions());
+        let mut system_prompt = prompts::render(&template, &context);
+        if let Some(instructions) = self.instructions {
+            system_prompt = format!(
+                "{}\n\nPROJECT INSTRUCTIONS:\nFollow these instructions provided by the user and their team.\n\n{}",
+                system_prompt, instructions
+            );
+        }
+
+        Assistant {
+            model: self.model,
+            options: self.options,
+            max_turns: self.max_turns,
+            backend: self
+                .backend
+                .unwrap_or_else(|| Box::new(OllamaClient::default())),
+            retry: self.retry,
+            timeouts: self.timeouts,
+            tools,
+            native_tools: self.native_tools,
+            json_format: prompts::asks_for_json(&template),
+            conversation: vec![ChatMessage::system(system_prompt)],
+            event_handlers: self.event_handlers,
+            turn_limit_callback: self.turn_limit_callback,
+            approval_callback: self.approval_callback,
+            stats: TurnStats::default(),
+        }
+    }
+}
+
+impl Assistant {
+    pub fn builder(model: impl Into<String>) -> AssistantBuilder {
+        AssistantBuilder::new(model)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn options(&self) -> &ModelOptions {
+        &self.options
+    }
+
+    /// Changes a model option for the following requests
+    pub fn set_option(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.options.set(key, value)
+    }
+
+    pub fn conversation(&self) -> &[ChatMessage] {
+        &self.conversation
+    }
+
+    /// Statistics for the last question, also when it failed
+    pub fn stats(&self) -> &TurnStats {
+        &self.stats
+    }
+
+    /// Runs one turn of the conversation. If `cancel` fires before the turn
+    /// completes, the pending request or tool call is abandoned. On
+    /// cancellation or error the conversation is rolled back to where it was
+    /// before the question was asked.
+    #[tracing::instrument(skip(self, cancel))]
+    pub async fn ask(
+        &mut self,
+        question: &str,
+        cancel: &CancellationToken,
+    ) -> Result<String, AssistantError> {
+        let checkpoint = self.conversation.len();
+
+        let result = tokio::select! {
+            result = self.run_turn(question) => result,
+            _ = cancel.cancelled() => Err(AssistantError::Cancelled),
+        };
+
+        if result.is_err() {
+            self.conversation.truncate(checkpoint);
+        }
+        self.emit(AssistantEvent::TurnFinished {
+            usage: self.stats.clone(),
+        });
+        result
+    }
+
+    /// The span records how often the model repeated itself, so looping can
+    /// be compared across models.
+    #[tracing::instrument(
+        skip(self, question),
+        fields(model = %self.model, turns, repeated_calls, stagnated)
+    )]
+    async fn run_turn(&mut self, question: &str) -> Result<String, AssistantError> {
         // Add user message
-        self.conversation.push(HashMap::from([
-            (ROLE.into(), Value::String(USER.into())),
-            (CONTENT.into(), Value::String(question.to_string())),
-        ]));
+        self.conversation.push(ChatMessage::user(question));
+        self.stats = TurnStats::default();
+        self.emit(AssistantEvent::TurnStarted {
+            question: question.to_string(),
+        });
+
+        let mut tracker = RepetitionTracker::default();
+        let result = self.agent_loop(&mut tracker).await;
+
+        let span = tracing::Span::current();
+        span.record("repeated_calls", tracker.repeated_calls());
+        result
+    }
 
+    async fn agent_loop(
+        &mut self,
+        tracker: &mut RepetitionTracker,
+    ) -> Result<String, AssistantError> {
         // Process until we get a final answer (with safety limit)
-        let mut loop_count = 0;
+        let mut turns = 0;
+        let mut budget = self.max_turns;
         loop {
-            loop_count += 1;
-            if loop_count > 10 {
-                return Err(AssistantError::ToolError(
-                    "Too many iterations, model not providing final answer".into(),
-                ));
+            if turns >= budget {
+                let extra = match &self.turn_limit_callback {
+                    Some(callback) => callback(turns).await,
+                    None => 0,
+                };
+                if extra == 0 {
+                    return self.wrap_up().await;
+                }
+                budget += extra;
             }
-            let response = self.get_model_response().await?;
+            turns += 1;
+            tracing::Span::current().record("turns", turns);
+
+            let response = self.get_model_response(true).await?;
+            let mut message = response.message;
+            assign_call_ids(&mut message.tool_calls, self.conversation.len());
 
-            if let Some(tool_calls) = response.get(TOOL_CALLS) {
+            if !message.tool_calls.is_empty() {
                 // Add the assistant's tool call message to conversation
-                self.conversation.push(HashMap::from([
-                    (ROLE.into(), Value::String(ASSISTANT.into())),
-                    (TOOL_CALLS.into(), tool_calls.clone()),
-                ]));
+                self.conversation
+                    .push(ChatMessage::tool_calls(message.tool_calls.clone()));
 
                 // Execute tools and add results
-                self.execute_tools(tool_calls).await?;
+                let new_calls = self.execute_tools(&message.tool_calls, tracker).await?;
+                if tracker.end_round(new_calls) {
+                    return self.stop_looping().await;
+                }
                 // Continue loop to get model's response to tool results
                 continue;
-            } else if let Some(content) = response.get(CONTENT) {
-                // Got final answer
-                self.conversation.push(HashMap::from([
-                    (ROLE.into(), Value::String(ASSISTANT.into())),
-                    (CONTENT.into(), content.clone()),
-                ]));
-                return Ok(content.as_str().unwrap_or("").to_string());
-            } else {
-                return Err(AssistantError::ToolError(format!(
-                    "Invalid response format: {:?}",
-                    response
-                )));
             }
+
+            // Many models write their tool calls into the content instead
+            let tool_names = self.tools.names();
+            let known_tools: Vec<&str> = tool_names.iter().map(String::as_str).collect();
+            let mut parsed = tool_parser::parse_tool_calls(&message.content, &known_tools);
+            assign_call_ids(&mut parsed, self.conversation.len());
+            if !parsed.is_empty() {
+                tracing::info!(count = parsed.len(), "parsed tool calls from content");
+                if self.native_tools {
+                    self.conversation
+                        .push(ChatMessage::tool_calls(parsed.clone()));
+                } else {
+                    // The model's chat template might not know about tool
+                    // calls, keep the message the way the model wrote it
+                    self.conversation
+                        .push(ChatMessage::assistant(message.content.clone()));
+                }
+                let new_calls = self.execute_tools(&parsed, tracker).await?;
+                if tracker.end_round(new_calls) {
+                    return self.stop_looping().await;
+                }
+                continue;
+            }
+
+            if message.content.trim().is_empty() {
+                return Err(empty_response());
+            }
+            // Got final answer
+            self.conversation
+                .push(ChatMessage::assistant(message.content.clone()));
+            return Ok(message.content);
         }
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn get_model_response(&self) -> Result<HashMap<String, Value>, AssistantError> {
-        let response = self
-            .client
-            .post("http://localhost:11434/api/chat")
-            .json(&json!({
-                "model": self.model,
-                "messages": self.conversation,
-                "tools": [
-                    {
-                        "type": "function",
-                        "function": {
-                            "name": "list_directory",
-                            "description": "List files and directories",
-                            "parameters": {
-                                "type": "object",
-                                "properties": {
-                                    "path": {
-                                        "type": "string",
-                                        "description": "Directory path"
-                                    }
-                                },
-                                "required": ["path"]
-                            }
-                        }
-                    },
-                    {
-                        "type": "function",
-                        "function": {
-                            "name": "read_file",
-                            "description": "Read file contents",
-                            "parameters": {
-                                "type": "object",
-                                "properties": {
-                                    "path": {
-                                        "type": "string",
-                                        "description": "File path"
-                                    }
-                                },
-                                "required": ["path"]
-                            }
-                        }
-                    },
-                    {
-                        "type": "function",
-                        "function": {
-                            "name": "pwd",
-                            "description": "Returns the full path of the current directory"
-                        }
-                    },
-                    {
-                        "type": "function",
-                        "function": {
-                            "name": "grep",
-                            "description": "Searches for a specific substring in a designated file",
-                            "parameters": {
-                                "type": "object",
-                                "properties": {
-                                    "path": {
-                                        "type": "string",
-                                        "description": "The file to grep through"
-                                    },
-                                    "search_pattern": {
-                                        "type": "string",
-                                        "description": "The search pattern"
-                                    }
-                                },
-                                "required": ["path", "search_pattern"]
-                            }
-                        }
-                    }
-                ],
-                "stream": false,  // Key change: no streaming
-                "format": "json",
-                "options": {
-                    "temperature": 0.5,
-                    "num_ctx": 32768  // Use much larger context window
-                }
-            }))
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+    /// Out of turns: asks the model for a final answer based on what it
+    /// found so far, without offering any tools.
+    async fn wrap_up(&mut self) -> Result<String, AssistantError> {
+        self.emit(AssistantEvent::WrappingUp {
+            reason: WrapUpReason::TurnLimit {
+                turns: self.stats.requests,
+            },
+        });
+        self.final_answer().await
+    }
+
+    /// The model keeps making the same calls without getting anywhere. More
+    /// turns won't help, so it has to answer.
+    async fn stop_looping(&mut self) -> Result<String, AssistantError> {
+        tracing::warn!("model is going in circles");
+        tracing::Span::current().record("stagnated", true);
+        self.emit(AssistantEvent::WrappingUp {
+            reason: WrapUpReason::Looping,
+        });
+        self.final_answer().await
+    }
 
-        // Extract the response message
-        let message = response["message"]
-            .as_object()
-            .ok_or(AssistantError::ToolError(format!(
-                "{} - No message in response",
-                response
-            )))?;
+    #[tracing::instrument(skip(self))]
+    async fn final_answer(&mut self) -> Result<String, AssistantError> {
+        self.conversation.push(ChatMessage::user(WRAP_UP_PROMPT));
 
-        Ok(message
-            .into_iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect())
+        let response = self.get_model_response(false).await?;
+        let content = response.message.content;
+        if content.trim().is_empty() {
+            return Err(empty_response());
+        }
+        self.conversation
+            .push(ChatMessage::assistant(content.clone()));
+        Ok(content)
     }
 
     #[tracing::instrument(skip(self))]
-    async fn execute_tools(&mut self, tool_calls: &Value) -> Result<(), AssistantError> {
-        let calls = tool_calls.as_array().ok_or(AssistantError::ToolError(
-            "Invalid tool_calls format".into(),
-        ))?;
-
-        for call in calls {
-            let function = &call["function"];
-            let name = function["name"]
-                .as_str()
-                .ok_or(AssistantError::ToolError("Missing function name".into()))?;
-            let args = &function["arguments"];
-
-            let result = match name {
-                "list_directory" => {
-                    let path = args["path"].as_str().unwrap_or(".");
-                    if let Some(ref callback) = self.progress_callback {
-                        callback(&format!("📁 Listing directory: {}", path));
-                    }
-                    match self.toolchain.call(Tool::ReadDirectory(path.to_string())) {
-                        Ok(result) => {
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   Found {} items", result.lines().count()));
-                            }
-                            result
-                        }
-                        Err(e) => {
-                            let error_msg = format!("ERROR: Could not list directory '{}' - {}", path, e);
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   ❌ {}", error_msg));
-                            }
-                            error_msg
-                        }
-                    }
+    async fn get_model_response(
+        &mut self,
+        with_tools: bool,
+    ) -> Result<ChatResponse, AssistantError> {
+        let request = ChatRequest {
+            tools: if self.native_tools && with_tools {
+                self.tools.definitions()
+            } else {
+                Vec::new()
+            },
+            format: self.json_format.then(|| json!("json")),
+            options: Some(self.options.request_options()),
+            keep_alive: self.options.request_keep_alive(),
+            ..ChatRequest::new(&self.model, self.conversation.clone())
+        };
+
+        self.emit(AssistantEvent::ModelRequest {
+            turn: self.stats.requests + 1,
+            with_tools,
+        });
+        let mut retries = 0;
+        let response = loop {
+            match self.request(&request).await {
+                Ok(response) => break response,
+                Err(err) if err.is_transient() && retries < self.retry.max_retries => {
+                    retries += 1;
+                    let delay = self.retry.delay(retries);
+                    tracing::warn!(%err, retries, ?delay, "retrying model request");
+                    self.emit(AssistantEvent::Retrying {
+                        retry: retries,
+                        max_retries: self.retry.max_retries,
+                        delay,
+                        error: err.to_string(),
+                    });
+                    tokio::time::sleep(delay).await;
                 }
-                "read_file" => {
-                    let path = args["path"].as_str().unwrap_or(".");
-                    if let Some(ref callback) = self.progress_callback {
-                        callback(&format!("📄 Reading file: {}", path));
-                    }
-                    match self.toolchain.call(Tool::ReadFile(path.to_string())) {
-                        Ok(result) => {
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   Read {} characters", result.len()));
-                            }
-                            result
-                        }
-                        Err(e) => {
-                            let error_msg = format!("ERROR: Could not read file '{}' - {}", path, e);
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   ❌ {}", error_msg));
-                            }
-                            error_msg
-                        }
-                    }
+                Err(err) => return Err(err),
+            }
+        };
+        self.stats.requests += 1;
+        self.stats.prompt_tokens += response.prompt_eval_count.unwrap_or(0);
+        self.stats.completion_tokens += response.eval_count.unwrap_or(0);
+        Ok(response)
+    }
+
+    async fn request(&self, request: &ChatRequest) -> Result<ChatResponse, AssistantError> {
+        let stream = self
+            .timeouts
+            .chat_stream(self.backend.as_ref(), request)
+            .await?;
+        let response = backend::collect_with(stream, |content| {
+            self.emit(AssistantEvent::TokenDelta {
+                content: content.to_string(),
+            })
+        })
+        .await?;
+        Ok(response)
+    }
+
+    /// Runs the tool calls and adds their results to the conversation, in
+    /// call order. Consecutive read-only calls run concurrently, other calls
+    /// run one at a time. Calls that can't be run, e.g. because of invalid
+    /// arguments, get an error result instead, so the model can correct
+    /// itself. Repeated read-only calls get their earlier result and a nudge
+    /// to move on, until a call changes the workspace. Returns how many of
+    /// the calls were new, or an error when a tool is broken.
+    #[tracing::instrument(skip(self, tracker))]
+    async fn execute_tools(
+        &mut self,
+        tool_calls: &[ToolCall],
+        tracker: &mut RepetitionTracker,
+    ) -> Result<usize, AssistantError> {
+        let prepared: Vec<Result<PreparedCall, String>> = tool_calls
+            .iter()
+            .map(|call| self.tools.prepare(call))
+            .collect();
+        let mut results: Vec<Option<String>> = tool_calls
+            .iter()
+            .zip(&prepared)
+            .map(|(call, tool)| {
+                if !is_cacheable(tool) {
+                    return None;
                 }
-                "pwd" => {
-                    if let Some(ref callback) = self.progress_callback {
-                        callback("📍 Getting current directory...");
-                    }
-                    match self.toolchain.call(Tool::CurrentDir) {
-                        Ok(result) => {
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   Current directory: {}", result.trim()));
-                            }
-                            result
-                        }
-                        Err(e) => {
-                            let error_msg = format!("ERROR: Could not get current directory - {}", e);
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   ❌ {}", error_msg));
-                            }
-                            error_msg
-                        }
-                    }
+                let repeated = tracker.repeated(call);
+                if repeated.is_some() {
+                    self.emit(AssistantEvent::ToolCallRepeated {
+                        id: call.id.clone(),
+                        name: call.function.name.clone(),
+                    });
                 }
-                "grep" => {
-                    let path = args["path"].as_str().ok_or_else(|| {
-                        AssistantError::ToolError("Missing Grep Parameter 'path'".into())
-                    })?;
-                    let search_pattern = args["search_pattern"].as_str().ok_or_else(|| {
-                        AssistantError::ToolError("Missing Grep Parameter 'search_pattern'".into())
-                    })?;
-
-                    if let Some(ref callback) = self.progress_callback {
-                        callback(&format!(
-                            "🔍 Searching for '{}' in {}",
-                            search_pattern, path
-                        ));
-                    }
-
-                    match self.toolchain.call(Tool::Grep {
-                        search_string: search_pattern.into(),
-                        path: path.into(),
-                    }) {
-                        Ok(result) => {
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   Search completed"));
-                            }
-                            result
-                        }
-                        Err(e) => {
-                            let error_msg = format!("ERROR: Could not search in file '{}' - {}", path, e);
-                            if let Some(ref callback) = self.progress_callback {
-                                callback(&format!("   ❌ {}", error_msg));
-                            }
-                            error_msg
-                        }
-                    }
+                repeated
+            })
+            .collect();
+        let fresh: Vec<(usize, &ToolCall, Result<PreparedCall, String>)> = tool_calls
+            .iter()
+            .zip(prepared)
+            .enumerate()
+            .filter(|(index, _)| results[*index].is_none())
+            .map(|(index, (call, tool))| (index, call, tool))
+            .collect();
+        let new_calls = fresh.len();
+        self.stats.tool_calls += tool_calls.len();
+        // Rejected calls don't touch anything, they can be answered in any
+        // order. Calls that need approval are asked about one at a time.
+        let concurrent = |tool: &Result<PreparedCall, String>| match tool {
+            Ok(tool) => tool.is_read_only() && !tool.needs_approval(),
+            Err(_) => true,
+        };
+
+        let mut pending = fresh.into_iter().peekable();
+        while let Some((index, call, tool)) = pending.next() {
+            if !concurrent(&tool) {
+                let cacheable = is_cacheable(&tool);
+                let mutating = matches!(&tool, Ok(tool) if !tool.is_read_only());
+                let result = self.execute_call(call, tool).await;
+                if mutating {
+                    tracker.forget_results();
                 }
-                _ => return Err(AssistantError::ToolError(format!("Unknown tool: {}", name))),
-            };
+                results[index] = Some(self.record_result(call, result, cacheable, tracker)?);
+                continue;
+            }
+
+            let mut batch = vec![(index, call, tool)];
+            while let Some(next) = pending.next_if(|(_, _, tool)| concurrent(tool)) {
+                batch.push(next);
+            }
+            tracing::debug!(size = batch.len(), "running tool calls concurrently");
+            let batch_results = join_all(
+                batch
+                    .iter()
+                    .map(|(_, call, tool)| self.execute_call(call, tool.clone())),
+            )
+            .await;
+            for ((index, call, tool), result) in batch.into_iter().zip(batch_results) {
+                let cacheable = is_cacheable(&tool);
+                results[index] = Some(self.record_result(call, result, cacheable, tracker)?);
+            }
+        }
 
+        for (call, result) in tool_calls.iter().zip(results) {
+            let result = result.unwrap_or_default();
             // Add tool result to conversation
-            self.conversation.push(HashMap::from([
-                ("role".into(), Value::String("tool".into())),
-                ("content".into(), Value::String(result)),
-                ("name".into(), Value::String(name.to_string())),
-            ]));
+            let message = if self.native_tools {
+                ChatMessage::tool_result(call, result)
+            } else {
+                // Without native tool support, role "tool" messages may be
+                // dropped by the chat template
+                ChatMessage::user(format!("Result of {}:\n{}", call.function.name, result))
+            };
+            self.conversation.push(message);
         }
+        Ok(new_calls)
+    }
+
+    /// Counts failed calls. Only successful results of cacheable calls are
+    /// kept for repeated calls, failures might not happen again.
+    fn record_result(
+        &mut self,
+        call: &ToolCall,
+        result: Result<String, ToolError>,
+        cacheable: bool,
+        tracker: &mut RepetitionTracker,
+    ) -> Result<String, AssistantError> {
+        match result {
+            Ok(output) => {
+                if cacheable {
+                    tracker.record(call, &output);
+                }
+                Ok(output)
+            }
+            Err(ToolError::Failed(error)) => {
+                self.stats.tool_errors += 1;
+                Ok(error)
+            }
+            Err(ToolError::Broken(message)) => {
+                self.stats.tool_errors += 1;
+                Err(AssistantError::ToolFailure {
+                    tool: call.function.name.clone(),
+                    message,
+                })
+            }
+        }
+    }
+
+    /// The result for the model to read. Failed calls are results too, but
+    /// are counted separately.
+    async fn execute_call(
+        &self,
+        call: &ToolCall,
+        tool: Result<PreparedCall, String>,
+    ) -> Result<String, ToolError> {
+        self.emit(AssistantEvent::ToolCallStarted {
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+            arguments: call.function.arguments.clone(),
+        });
+        let started = Instant::now();
 
-        Ok(())
+        let (result, error) = match tool {
+            Ok(tool) if tool.needs_approval() && !self.approve(call).await => {
+                tracing::info!(tool = call.function.name, "tool call denied");
+                let result = format!(
+                    "ERROR: The user did not allow this call to {}. \
+                    Don't try it again, do without it or ask the user.",
+                    call.function.name
+                );
+                (
+                    Err(ToolError::Failed(result)),
+                    Some("Denied by the user".to_string()),
+                )
+            }
+            Ok(tool) => match tool.run().await {
+                Ok(output) => (Ok(output), None),
+                Err(ToolError::Failed(error)) => (
+                    Err(ToolError::Failed(format!("ERROR: {}", error))),
+                    Some(error),
+                ),
+                Err(ToolError::Broken(error)) => {
+                    tracing::error!(tool = call.function.name, %error, "tool is broken");
+                    (Err(ToolError::Broken(error.clone())), Some(error))
+                }
+            },
+            Err(error) => {
+                tracing::warn!(tool = call.function.name, %error, "rejected tool call");
+                let result = format!("ERROR: {}\nFix the call and try again.", error);
+                (Err(ToolError::Failed(result)), Some(error))
+            }
+        };
+
+        self.emit(AssistantEvent::ToolCallFinished {
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+            result: match &result {
+                Ok(output) => output.clone(),
+                Err(error) => error.to_string(),
+            },
+            duration: started.elapsed(),
+            error,
+        });
+        result
+    }
+
+    async fn approve(&self, call: &ToolCall) -> bool {
+        match &self.approval_callback {
+            Some(callback) => callback(call).await,
+            None => false,
+        }
+    }
+
+    fn emit(&self, event: AssistantEvent) {
+        for handler in &self.event_handlers {
+            handler(&event);
+        }
+    }
+}
+
+/// Some models get confused and answer with nothing at all. Passing that on
+/// as the answer would look like success.
+fn empty_response() -> AssistantError {
+    AssistantError::InvalidResponse(OllamaError::MalformedResponseError(
+        "the model returned neither an answer nor tool calls".into(),
+    ))
+}
+
+/// Whether a repeated call may get the earlier result instead of running
+/// again. Calls that change something, or that the user has to approve, are
+/// always run.
+fn is_cacheable(tool: &Result<PreparedCall, String>) -> bool {
+    matches!(tool, Ok(tool) if tool.is_read_only() && !tool.needs_approval())
+}
+
+/// OpenAI compatible servers match tool results to calls by id. Calls that
+/// come without one, e.g. parsed from the content, get an id that is unique
+/// within the conversation.
+fn assign_call_ids(calls: &mut [ToolCall], position: usize) {
+    for (index, call) in calls.iter_mut().enumerate() {
+        if call.id.is_none() {
+            call.id = Some(format!("call_{}_{}", position, index));
+        }
     }
 }
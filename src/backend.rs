@@ -0,0 +1,216 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use futures::{FutureExt, StreamExt, future::BoxFuture, stream::BoxStream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ollama::{ChatRequest, ChatResponse, OllamaClient, OllamaError},
+    openai::OpenAiClient,
+};
+
+pub type ChatStream = BoxStream<'static, Result<ChatResponse, OllamaError>>;
+
+/// A server the assistant can chat with. Implementations translate from and
+/// to the Ollama request and response types.
+pub trait ChatBackend: Send + Sync {
+    /// Where requests go, for logs and error messages
+    fn describe(&self) -> String;
+
+    /// Streams the response. The last chunk has `done` set.
+    fn chat_stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+    ) -> BoxFuture<'a, Result<ChatStream, OllamaError>>;
+}
+
+impl ChatBackend for OllamaClient {
+    fn describe(&self) -> String {
+        format!("ollama at {}", self.base_url())
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+    ) -> BoxFuture<'a, Result<ChatStream, OllamaError>> {
+        async move { Ok(OllamaClient::chat_stream(self, request).await?.boxed()) }.boxed()
+    }
+}
+
+impl ChatBackend for OpenAiClient {
+    fn describe(&self) -> String {
+        format!("OpenAI compatible server at {}", self.base_url())
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+    ) -> BoxFuture<'a, Result<ChatStream, OllamaError>> {
+        async move { Ok(OpenAiClient::chat_stream(self, request).await?.boxed()) }.boxed()
+    }
+}
+
+/// Which API the assistant talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Ollama,
+    /// Any server with an OpenAI compatible chat completions API
+    #[value(name = "openai")]
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "ollama" => Ok(BackendKind::Ollama),
+            "openai" => Ok(BackendKind::OpenAi),
+            _ => Err(format!("Unknown backend '{}'", value)),
+        }
+    }
+}
+
+impl Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendKind::Ollama => write!(f, "ollama"),
+            BackendKind::OpenAi => write!(f, "openai"),
+        }
+    }
+}
+
+/// Puts a streamed response back together: the content of all chunks, all
+/// tool calls, and the statistics of the final chunk.
+pub async fn collect(stream: ChatStream) -> Result<ChatResponse, OllamaError> {
+    collect_with(stream, |_| {}).await
+}
+
+/// Like `collect`, but passes each piece of content on as it arrives
+pub async fn collect_with(
+    mut stream: ChatStream,
+    mut on_content: impl FnMut(&str),
+) -> Result<ChatResponse, OllamaError> {
+    let mut response: Option<ChatResponse> = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if !chunk.message.content.is_empty() {
+            on_content(&chunk.message.content);
+        }
+        response = Some(match response {
+            None => chunk,
+            Some(mut merged) => {
+                merged.message.content.push_str(&chunk.message.content);
+                merged.message.tool_calls.extend(chunk.message.tool_calls);
+                ChatResponse {
+                    message: merged.message,
+                    ..chunk
+                }
+            }
+        });
+    }
+    response.ok_or_else(|| OllamaError::MalformedResponseError("empty response stream".into()))
+}
+
+/// How long to wait in each phase of a request. Loading a model counts
+/// towards the first two, which is why they are generous.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Until the server answers the request
+    pub request: Duration,
+    /// From the answer until the first chunk of the response
+    pub first_token: Duration,
+    /// Between two chunks of the response
+    pub idle: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            request: Duration::from_secs(300),
+            first_token: Duration::from_secs(300),
+            idle: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Starts a request, giving up when the server doesn't answer in time
+    pub async fn chat_stream(
+        &self,
+        backend: &dyn ChatBackend,
+        request: &ChatRequest,
+    ) -> Result<ChatStream, OllamaError> {
+        let stream = tokio::time::timeout(self.request, backend.chat_stream(request))
+            .await
+            .map_err(|_| OllamaError::TimeoutError {
+                waiting_for: "response from the server",
+                after: self.request,
+            })??;
+        Ok(self.limit(stream))
+    }
+
+    /// Ends the stream with an error when the next chunk takes too long
+    fn limit(&self, stream: ChatStream) -> ChatStream {
+        let timeouts = *self;
+        futures::stream::unfold(Some((stream, true)), move |state| async move {
+            let (mut stream, first) = state?;
+            let (limit, waiting_for) = if first {
+                (timeouts.first_token, "first token")
+            } else {
+                (timeouts.idle, "next token")
+            };
+            match tokio::time::timeout(limit, stream.next()).await {
+                Ok(Some(chunk)) => Some((chunk, Some((stream, false)))),
+                Ok(None) => None,
+                Err(_) => {
+                    let error = OllamaError::TimeoutError {
+                        waiting_for,
+                        after: limit,
+                    };
+                    Some((Err(error), None))
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// How often to retry requests that failed for reasons that might go away,
+/// such as a server that is still starting. The delay doubles with every
+/// attempt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Doesn't retry at all
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// The delay before the given retry, counting from 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
@@ -0,0 +1,1055 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{BackendKind, RetryPolicy, Timeouts},
+    mcp::McpServerConfig,
+    ollama, openai,
+};
+
+pub const ENV_PREFIX: &str = "OLLAMA_CODE_";
+const CONFIG_FILE: &str = "config.toml";
+const TRUSTED_SERVERS_FILE: &str = "trusted_mcp_servers.json";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    InvalidEnv(String, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            ConfigError::Parse(path, error) => write!(f, "{}: {}", path.display(), error),
+            ConfigError::InvalidEnv(name, value) => {
+                write!(f, "Invalid value for {}: '{}'", name, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// One layer of configuration. Every field is optional, so a layer only
+/// overrides what it sets. This is both the format of the config files and
+/// what the CLI and environment are turned into.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    pub model: Option<String>,
+    pub backend: Option<BackendKind>,
+    pub ollama_url: Option<String>,
+    pub openai_url: Option<String>,
+    pub api_key: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub max_turns: Option<usize>,
+    /// The old name of `max_turns`, still read but deprecated
+    #[serde(skip_serializing)]
+    pub max_iterations: Option<usize>,
+    pub max_retries: Option<u32>,
+    pub request_timeout: Option<u64>,
+    pub first_token_timeout: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    pub system_prompt_file: Option<PathBuf>,
+    pub color: Option<bool>,
+    pub pull: Option<bool>,
+    pub options: Option<OptionsLayer>,
+    /// A server defined in a later layer replaces one of the same name
+    pub mcp_servers: Option<BTreeMap<String, McpServerConfig>>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OptionsLayer {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<u64>,
+    pub seed: Option<i64>,
+    pub num_ctx: Option<u64>,
+    pub num_predict: Option<i64>,
+    pub repeat_penalty: Option<f64>,
+    pub stop: Option<Vec<String>>,
+    pub keep_alive: Option<String>,
+}
+
+/// Where a configuration value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env,
+    Cli,
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// Options passed to the model with every chat request. Unset options are
+/// left to the model's defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelOptions {
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    pub num_ctx: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub stop: Vec<String>,
+    /// How long the model stays loaded after a request, e.g. "5m" or "-1".
+    /// Sent next to the options rather than inside them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+}
+
+impl Default for ModelOptions {
+    fn default() -> Self {
+        Self {
+            temperature: 0.5,
+            top_p: None,
+            top_k: None,
+            seed: None,
+            num_ctx: 32768,
+            num_predict: None,
+            repeat_penalty: None,
+            stop: Vec::new(),
+            keep_alive: None,
+        }
+    }
+}
+
+impl ModelOptions {
+    pub const KEYS: [&str; 9] = [
+        "temperature",
+        "top_p",
+        "top_k",
+        "seed",
+        "num_ctx",
+        "num_predict",
+        "repeat_penalty",
+        "stop",
+        "keep_alive",
+    ];
+
+    /// Changes a single option. `unset` (or an empty value) removes optional
+    /// ones. Stop sequences are given comma separated.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let unset = value.is_empty() || value == "unset";
+        match key {
+            "temperature" => self.temperature = parse(key, value)?,
+            "top_p" => self.top_p = parse_optional(key, value, unset)?,
+            "top_k" => self.top_k = parse_optional(key, value, unset)?,
+            "seed" => self.seed = parse_optional(key, value, unset)?,
+            "num_ctx" => self.num_ctx = parse(key, value)?,
+            "num_predict" => self.num_predict = parse_optional(key, value, unset)?,
+            "repeat_penalty" => self.repeat_penalty = parse_optional(key, value, unset)?,
+            "stop" => self.stop = if unset { Vec::new() } else { split_list(value) },
+            "keep_alive" => self.keep_alive = (!unset).then(|| value.to_string()),
+            _ => {
+                return Err(format!(
+                    "Unknown option '{}'. Available options: {}",
+                    key,
+                    Self::KEYS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `options` object of a chat request
+    pub fn request_options(&self) -> serde_json::Value {
+        let mut options = serde_json::to_value(self).unwrap_or_default();
+        if let Some(options) = options.as_object_mut() {
+            options.remove("keep_alive");
+        }
+        options
+    }
+
+    /// The `keep_alive` field of a chat request. Plain numbers are seconds.
+    pub fn request_keep_alive(&self) -> Option<serde_json::Value> {
+        self.keep_alive.as_ref().map(|keep_alive| {
+            keep_alive
+                .parse::<i64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| serde_json::Value::from(keep_alive.as_str()))
+        })
+    }
+}
+
+impl Display for ModelOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "temperature = {}", self.temperature)?;
+        writeln!(f, "top_p = {}", optional(&self.top_p))?;
+        writeln!(f, "top_k = {}", optional(&self.top_k))?;
+        writeln!(f, "seed = {}", optional(&self.seed))?;
+        writeln!(f, "num_ctx = {}", self.num_ctx)?;
+        writeln!(f, "num_predict = {}", optional(&self.num_predict))?;
+        writeln!(f, "repeat_penalty = {}", optional(&self.repeat_penalty))?;
+        writeln!(f, "stop = {:?}", self.stop)?;
+        write!(f, "keep_alive = {}", optional(&self.keep_alive))
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: '{}'", key, value))
+}
+
+fn parse_optional<T: FromStr>(key: &str, value: &str, unset: bool) -> Result<Option<T>, String> {
+    if unset {
+        Ok(None)
+    } else {
+        parse(key, value).map(Some)
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// The effective configuration, after merging defaults, the user config
+/// file, the project config file, environment variables and CLI arguments,
+/// in that order of precedence (later wins).
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub model: Setting<String>,
+    pub backend: Setting<BackendKind>,
+    pub ollama_url: Setting<String>,
+    pub openai_url: Setting<String>,
+    pub api_key: Setting<Option<String>>,
+    pub otlp_endpoint: Setting<String>,
+    pub max_turns: Setting<usize>,
+    pub max_retries: Setting<u32>,
+    /// Timeouts in seconds
+    pub request_timeout: Setting<u64>,
+    pub first_token_timeout: Setting<u64>,
+    pub idle_timeout: Setting<u64>,
+    pub system_prompt_file: Setting<Option<PathBuf>>,
+    pub color: Setting<bool>,
+    pub pull: Setting<bool>,
+    pub temperature: Setting<f64>,
+    pub top_p: Setting<Option<f64>>,
+    pub top_k: Setting<Option<u64>>,
+    pub seed: Setting<Option<i64>>,
+    pub num_ctx: Setting<u64>,
+    pub num_predict: Setting<Option<i64>>,
+    pub repeat_penalty: Setting<Option<f64>>,
+    pub stop: Setting<Vec<String>>,
+    pub keep_alive: Setting<Option<String>>,
+    pub mcp_servers: BTreeMap<String, Setting<McpServerConfig>>,
+    /// Problems with the configuration that don't stop it from loading,
+    /// e.g. deprecated keys
+    pub warnings: Vec<String>,
+}
+
+impl Config {
+    pub fn load(workspace: &Path, cli: ConfigLayer) -> Result<Self, ConfigError> {
+        let mut layers = Vec::new();
+        let mut warnings = Vec::new();
+
+        if let Some(path) = user_config_file()
+            && let Some(layer) = read_layer(&path)?
+        {
+            layers.push((Source::File(path), layer));
+        }
+        if let Some(layer) = read_project_layer(workspace, &mut warnings)? {
+            layers.push((Source::File(project_config_file(workspace)), layer));
+        }
+        layers.push((Source::Env, env_layer()?));
+        layers.push((Source::Cli, cli));
+
+        let mut config = Self::merge(&layers);
+        config.warnings.splice(0..0, warnings);
+        Ok(config)
+    }
+
+    fn merge(layers: &[(Source, ConfigLayer)]) -> Self {
+        let options = ModelOptions::default();
+        let retry = RetryPolicy::default();
+        let timeouts = Timeouts::default();
+        Self {
+            model: pick(layers, "llama3.1:8b".into(), |l| l.model.clone()),
+            backend: pick(layers, BackendKind::Ollama, |l| l.backend),
+            ollama_url: pick(layers, ollama::DEFAULT_URL.into(), |l| l.ollama_url.clone()),
+            openai_url: pick(layers, openai::DEFAULT_URL.into(), |l| l.openai_url.clone()),
+            api_key: pick(layers, None, |l| l.api_key.clone().map(Some)),
+            otlp_endpoint: pick(layers, "http://localhost:4317".into(), |l| {
+                l.otlp_endpoint.clone()
+            }),
+            max_turns: pick(layers, 10, |l| l.max_turns.or(l.max_iterations)),
+            max_retries: pick(layers, retry.max_retries, |l| l.max_retries),
+            request_timeout: pick(layers, timeouts.request.as_secs(), |l| l.request_timeout),
+            first_token_timeout: pick(layers, timeouts.first_token.as_secs(), |l| {
+                l.first_token_timeout
+            }),
+            idle_timeout: pick(layers, timeouts.idle.as_secs(), |l| l.idle_timeout),
+            system_prompt_file: pick(layers, None, |l| l.system_prompt_file.clone().map(Some)),
+            color: pick(layers, true, |l| l.color),
+            pull: pick(layers, false, |l| l.pull),
+            temperature: pick(layers, options.temperature, |l| {
+                l.options.as_ref()?.temperature
+            }),
+            top_p: pick(layers, None, |l| l.options.as_ref()?.top_p.map(Some)),
+            top_k: pick(layers, None, |l| l.options.as_ref()?.top_k.map(Some)),
+            seed: pick(layers, None, |l| l.options.as_ref()?.seed.map(Some)),
+            num_ctx: pick(layers, options.num_ctx, |l| l.options.as_ref()?.num_ctx),
+            num_predict: pick(layers, None, |l| l.options.as_ref()?.num_predict.map(Some)),
+            repeat_penalty: pick(layers, None, |l| {
+                l.options.as_ref()?.repeat_penalty.map(Some)
+            }),
+            stop: pick(layers, Vec::new(), |l| l.options.as_ref()?.stop.clone()),
+            keep_alive: pick(layers, None, |l| {
+                l.options.as_ref()?.keep_alive.clone().map(Some)
+            }),
+            mcp_servers: merge_mcp_servers(layers),
+            warnings: deprecations(layers),
+        }
+    }
+
+    /// The contents of `system_prompt_file`, if set
+    pub fn system_prompt(&self) -> Result<Option<String>, ConfigError> {
+        self.system_prompt_file
+            .value
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.clone(), err))
+            })
+            .transpose()
+    }
+
+    pub fn model_options(&self) -> ModelOptions {
+        ModelOptions {
+            temperature: self.temperature.value,
+            top_p: self.top_p.value,
+            top_k: self.top_k.value,
+            seed: self.seed.value,
+            num_ctx: self.num_ctx.value,
+            num_predict: self.num_predict.value,
+            repeat_penalty: self.repeat_penalty.value,
+            stop: self.stop.value.clone(),
+            keep_alive: self.keep_alive.value.clone(),
+        }
+    }
+
+    /// The configured MCP servers, including disabled ones
+    pub fn mcp_servers(&self) -> BTreeMap<String, McpServerConfig> {
+        self.mcp_servers
+            .iter()
+            .map(|(name, server)| (name.clone(), server.value.clone()))
+            .collect()
+    }
+
+    /// Whether the server is defined in the project config of the workspace,
+    /// which comes with the repository rather than from the user
+    pub fn is_project_mcp_server(&self, workspace: &Path, name: &str) -> bool {
+        self.mcp_servers
+            .get(name)
+            .is_some_and(|server| server.source == Source::File(project_config_file(workspace)))
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries.value,
+            ..RetryPolicy::default()
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            request: Duration::from_secs(self.request_timeout.value),
+            first_token: Duration::from_secs(self.first_token_timeout.value),
+            idle: Duration::from_secs(self.idle_timeout.value),
+        }
+    }
+
+    /// Renders the configuration as TOML-like lines, each annotated with
+    /// where the value came from.
+    pub fn show(&self) -> String {
+        let mut lines = Vec::new();
+        let mut line = |key: &str, value: String, source: &Source| {
+            let origin = match source {
+                Source::Default => "default".to_string(),
+                Source::File(path) => path.display().to_string(),
+                Source::Env => format!(
+                    "env {}{}",
+                    ENV_PREFIX,
+                    key.rsplit('.').next().unwrap_or(key).to_uppercase()
+                ),
+                Source::Cli => "command line".to_string(),
+            };
+            lines.push(format!("{} = {}  # {}", key, value, origin));
+        };
+
+        line("model", quoted(&self.model.value), &self.model.source);
+        line(
+            "backend",
+            quoted(&self.backend.value.to_string()),
+            &self.backend.source,
+        );
+        line(
+            "ollama_url",
+            quoted(&self.ollama_url.value),
+            &self.ollama_url.source,
+        );
+        line(
+            "openai_url",
+            quoted(&self.openai_url.value),
+            &self.openai_url.source,
+        );
+        // Never print the key itself
+        line(
+            "api_key",
+            if self.api_key.value.is_some() {
+                "(set)".into()
+            } else {
+                "(unset)".into()
+            },
+            &self.api_key.source,
+        );
+        line(
+            "otlp_endpoint",
+            quoted(&self.otlp_endpoint.value),
+            &self.otlp_endpoint.source,
+        );
+        line(
+            "max_turns",
+            self.max_turns.value.to_string(),
+            &self.max_turns.source,
+        );
+        line(
+            "max_retries",
+            self.max_retries.value.to_string(),
+            &self.max_retries.source,
+        );
+        line(
+            "request_timeout",
+            self.request_timeout.value.to_string(),
+            &self.request_timeout.source,
+        );
+        line(
+            "first_token_timeout",
+            self.first_token_timeout.value.to_string(),
+            &self.first_token_timeout.source,
+        );
+        line(
+            "idle_timeout",
+            self.idle_timeout.value.to_string(),
+            &self.idle_timeout.source,
+        );
+        line(
+            "system_prompt_file",
+            self.system_prompt_file
+                .value
+                .as_ref()
+                .map(|p| quoted(&p.display().to_string()))
+                .unwrap_or_else(|| "(built-in)".into()),
+            &self.system_prompt_file.source,
+        );
+        line("color", self.color.value.to_string(), &self.color.source);
+        line("pull", self.pull.value.to_string(), &self.pull.source);
+        line(
+            "options.temperature",
+            self.temperature.value.to_string(),
+            &self.temperature.source,
+        );
+        line(
+            "options.top_p",
+            optional(&self.top_p.value),
+            &self.top_p.source,
+        );
+        line(
+            "options.top_k",
+            optional(&self.top_k.value),
+            &self.top_k.source,
+        );
+        line(
+            "options.seed",
+            optional(&self.seed.value),
+            &self.seed.source,
+        );
+        line(
+            "options.num_ctx",
+            self.num_ctx.value.to_string(),
+            &self.num_ctx.source,
+        );
+        line(
+            "options.num_predict",
+            optional(&self.num_predict.value),
+            &self.num_predict.source,
+        );
+        line(
+            "options.repeat_penalty",
+            optional(&self.repeat_penalty.value),
+            &self.repeat_penalty.source,
+        );
+        line(
+            "options.stop",
+            format!("{:?}", self.stop.value),
+            &self.stop.source,
+        );
+        line(
+            "options.keep_alive",
+            self.keep_alive
+                .value
+                .as_deref()
+                .map(quoted)
+                .unwrap_or_else(|| "(unset)".into()),
+            &self.keep_alive.source,
+        );
+        for (name, server) in &self.mcp_servers {
+            let status = if server.value.enabled {
+                format!("permission: {}", server.value.permission)
+            } else {
+                "disabled".to_string()
+            };
+            line(
+                &format!("mcp_servers.{}", name),
+                format!("{} ({})", quoted(&server.value.command_line()), status),
+                &server.source,
+            );
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Warnings for deprecated keys, one per layer that uses them
+fn deprecations(layers: &[(Source, ConfigLayer)]) -> Vec<String> {
+    layers
+        .iter()
+        .filter(|(_, layer)| layer.max_iterations.is_some())
+        .map(|(source, _)| match source {
+            Source::File(path) => format!(
+                "max_iterations in {} is deprecated, use max_turns instead",
+                path.display()
+            ),
+            _ => format!(
+                "{0}MAX_ITERATIONS is deprecated, use {0}MAX_TURNS instead",
+                ENV_PREFIX
+            ),
+        })
+        .collect()
+}
+
+/// Takes the value from the last layer that sets it
+fn pick<T>(
+    layers: &[(Source, ConfigLayer)],
+    default: T,
+    get: impl Fn(&ConfigLayer) -> Option<T>,
+) -> Setting<T> {
+    layers
+        .iter()
+        .rev()
+        .find_map(|(source, layer)| {
+            get(layer).map(|value| Setting {
+                value,
+                source: source.clone(),
+            })
+        })
+        .unwrap_or(Setting {
+            value: default,
+            source: Source::Default,
+        })
+}
+
+fn merge_mcp_servers(
+    layers: &[(Source, ConfigLayer)],
+) -> BTreeMap<String, Setting<McpServerConfig>> {
+    let mut servers = BTreeMap::new();
+    for (source, layer) in layers {
+        for (name, server) in layer.mcp_servers.iter().flatten() {
+            servers.insert(
+                name.clone(),
+                Setting {
+                    value: server.clone(),
+                    source: source.clone(),
+                },
+            );
+        }
+    }
+    servers
+}
+
+/// MCP servers from project configs that the user agreed to start. A
+/// project config can come with any repository, so its servers only start
+/// once the user has seen them. Changing a server's definition takes another
+/// approval.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrustedServers {
+    /// Server definitions as JSON, by workspace
+    workspaces: BTreeMap<PathBuf, Vec<String>>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl TrustedServers {
+    /// Reads the list from the data directory. A missing or unreadable list
+    /// trusts nothing.
+    pub fn load() -> Self {
+        Self::load_from(trusted_servers_file())
+    }
+
+    pub fn load_from(path: Option<PathBuf>) -> Self {
+        let trusted = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self { path, ..trusted }
+    }
+
+    pub fn is_trusted(&self, workspace: &Path, name: &str, server: &McpServerConfig) -> bool {
+        self.workspaces
+            .get(&canonical(workspace))
+            .is_some_and(|servers| servers.contains(&fingerprint(name, server)))
+    }
+
+    pub fn trust(&mut self, workspace: &Path, name: &str, server: &McpServerConfig) {
+        let servers = self.workspaces.entry(canonical(workspace)).or_default();
+        // An earlier definition of the same server is no longer needed
+        let prefix = format!("{}=", name);
+        servers.retain(|trusted| !trusted.starts_with(&prefix));
+        servers.push(fingerprint(name, server));
+    }
+
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn fingerprint(name: &str, server: &McpServerConfig) -> String {
+    format!(
+        "{}={}",
+        name,
+        serde_json::to_string(server).unwrap_or_default()
+    )
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn quoted(value: &str) -> String {
+    format!("{:?}", value)
+}
+
+fn optional<T: Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "(unset)".into())
+}
+
+pub fn user_config_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("ollama_code").join(CONFIG_FILE))
+}
+
+pub fn trusted_servers_file() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("ollama_code").join(TRUSTED_SERVERS_FILE))
+}
+
+pub fn project_config_file(workspace: &Path) -> PathBuf {
+    workspace.join(".ollama_code").join(CONFIG_FILE)
+}
+
+fn read_layer(path: &Path) -> Result<Option<ConfigLayer>, ConfigError> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(ConfigError::Io(path.to_path_buf(), err)),
+    };
+    let mut layer: ConfigLayer =
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?;
+    // Paths in a config file are relative to the file, not to wherever
+    // ollama_code happens to run
+    if let (Some(file), Some(dir)) = (&layer.system_prompt_file, path.parent()) {
+        layer.system_prompt_file = Some(dir.join(file));
+    }
+    Ok(Some(layer))
+}
+
+/// Reads the project config without the settings that say where requests
+/// go and what they contain. The config comes with the repository, and
+/// could otherwise send the user's API key, a file of their choosing or the
+/// traces to a server the repository picks.
+fn read_project_layer(
+    workspace: &Path,
+    warnings: &mut Vec<String>,
+) -> Result<Option<ConfigLayer>, ConfigError> {
+    let path = project_config_file(workspace);
+    let Some(mut layer) = read_layer(&path)? else {
+        return Ok(None);
+    };
+
+    let ignored: Vec<&str> = [
+        ("ollama_url", layer.ollama_url.take().is_some()),
+        ("openai_url", layer.openai_url.take().is_some()),
+        ("api_key", layer.api_key.take().is_some()),
+        ("otlp_endpoint", layer.otlp_endpoint.take().is_some()),
+        (
+            "system_prompt_file",
+            layer.system_prompt_file.take().is_some(),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, set)| set.then_some(key))
+    .collect();
+    if !ignored.is_empty() {
+        warnings.push(format!(
+            "ignoring {} in {}, set them in your user config or the environment instead",
+            ignored.join(", "),
+            path.display()
+        ));
+    }
+    Ok(Some(layer))
+}
+
+fn env_layer() -> Result<ConfigLayer, ConfigError> {
+    let options = OptionsLayer {
+        temperature: env_var("TEMPERATURE")?,
+        top_p: env_var("TOP_P")?,
+        top_k: env_var("TOP_K")?,
+        seed: env_var("SEED")?,
+        num_ctx: env_var("NUM_CTX")?,
+        num_predict: env_var("NUM_PREDICT")?,
+        repeat_penalty: env_var("REPEAT_PENALTY")?,
+        stop: env_var::<String>("STOP")?.map(|stop| split_list(&stop)),
+        keep_alive: env_var("KEEP_ALIVE")?,
+    };
+
+    Ok(ConfigLayer {
+        model: env_var("MODEL")?,
+        backend: env_var("BACKEND")?,
+        ollama_url: env_var("OLLAMA_URL")?,
+        openai_url: env_var("OPENAI_URL")?,
+        api_key: env_var("API_KEY")?,
+        otlp_endpoint: env_var("OTLP_ENDPOINT")?,
+        max_turns: env_var("MAX_TURNS")?,
+        max_iterations: env_var("MAX_ITERATIONS")?,
+        max_retries: env_var("MAX_RETRIES")?,
+        request_timeout: env_var("REQUEST_TIMEOUT")?,
+        first_token_timeout: env_var("FIRST_TOKEN_TIMEOUT")?,
+        idle_timeout: env_var("IDLE_TIMEOUT")?,
+        system_prompt_file: env_var("SYSTEM_PROMPT_FILE")?,
+        color: env_var("COLOR")?,
+        pull: env_var("PULL")?,
+        options: Some(options),
+        mcp_servers: None,
+    })
+}
+
+fn env_var<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    let name = format!("{}{}", ENV_PREFIX, name);
+    match std::env::var(&name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidEnv(name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer_with_server(name: &str, command: &str) -> ConfigLayer {
+        ConfigLayer {
+            mcp_servers: Some(BTreeMap::from([(
+                name.to_string(),
+                McpServerConfig::new(command),
+            )])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tells_project_servers_apart() {
+        let workspace = Path::new("some/project");
+        let config = Config::merge(&[
+            (
+                Source::File(PathBuf::from("user/config.toml")),
+                layer_with_server("tickets", "tickets-mcp"),
+            ),
+            (
+                Source::File(project_config_file(workspace)),
+                layer_with_server("build", "./build-mcp"),
+            ),
+        ]);
+
+        assert!(config.is_project_mcp_server(workspace, "build"));
+        assert!(!config.is_project_mcp_server(workspace, "tickets"));
+        assert!(!config.is_project_mcp_server(workspace, "missing"));
+    }
+
+    #[test]
+    fn remembers_trusted_servers() {
+        let path =
+            std::env::temp_dir().join(format!("ollama_code-trust-{}.json", std::process::id()));
+        let workspace = Path::new("some/project");
+        let server = McpServerConfig::new("./build-mcp");
+
+        let mut trusted = TrustedServers::load_from(Some(path.clone()));
+        assert!(!trusted.is_trusted(workspace, "build", &server));
+        trusted.trust(workspace, "build", &server);
+        trusted.save().unwrap();
+
+        let trusted = TrustedServers::load_from(Some(path.clone()));
+        assert!(trusted.is_trusted(workspace, "build", &server));
+        assert!(!trusted.is_trusted(Path::new("other"), "build", &server));
+        assert!(!trusted.is_trusted(workspace, "deploy", &server));
+        // A changed definition needs another approval
+        let changed = McpServerConfig {
+            args: vec!["--everything".into()],
+            ..server.clone()
+        };
+        assert!(!trusted.is_trusted(workspace, "build", &changed));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn accepts_the_old_name_of_max_turns() {
+        let layer: ConfigLayer = toml::from_str("max_iterations = 4").unwrap();
+        let config = Config::merge(&[(Source::File(PathBuf::from("config.toml")), layer)]);
+
+        assert_eq!(config.max_turns.value, 4);
+        assert_eq!(
+            config.warnings,
+            ["max_iterations in config.toml is deprecated, use max_turns instead"]
+        );
+        // The new name wins when both are set
+        let layer: ConfigLayer = toml::from_str("max_turns = 6\nmax_iterations = 4").unwrap();
+        let config = Config::merge(&[(Source::Env, layer)]);
+        assert_eq!(config.max_turns.value, 6);
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let layer = |model: &str, seed: i64| ConfigLayer {
+            model: Some(model.into()),
+            options: Some(OptionsLayer {
+                seed: Some(seed),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let workspace = Path::new("some/project");
+        let mut layers = vec![
+            (
+                Source::File(PathBuf::from("user/config.toml")),
+                layer("user", 1),
+            ),
+            (
+                Source::File(project_config_file(workspace)),
+                layer("project", 2),
+            ),
+            (Source::Env, layer("env", 3)),
+            (Source::Cli, layer("cli", 4)),
+        ];
+
+        while let Some((source, layer)) = layers.last().cloned() {
+            let config = Config::merge(&layers);
+            assert_eq!(config.model.value, layer.model.unwrap());
+            assert_eq!(config.model.source, source);
+            assert_eq!(config.seed.value, layer.options.unwrap().seed);
+            layers.pop();
+        }
+
+        let config = Config::merge(&layers);
+        assert_eq!(config.model.value, "llama3.1:8b");
+        assert_eq!(config.model.source, Source::Default);
+        assert_eq!(config.seed.value, None);
+        // Unset values fall through to earlier layers
+        let config = Config::merge(&[
+            (Source::Env, layer("env", 3)),
+            (Source::Cli, ConfigLayer::default()),
+        ]);
+        assert_eq!(config.model.value, "env");
+        assert_eq!(config.model.source, Source::Env);
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        let vars = [
+            ("OLLAMA_CODE_MODEL", "qwen2.5-coder:7b"),
+            ("OLLAMA_CODE_BACKEND", "openai"),
+            ("OLLAMA_CODE_MAX_TURNS", "4"),
+            ("OLLAMA_CODE_PULL", "true"),
+            ("OLLAMA_CODE_TEMPERATURE", "0.2"),
+            ("OLLAMA_CODE_STOP", "<|im_end|>, ,</s>"),
+        ];
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            for (name, value) in vars {
+                std::env::set_var(name, value);
+            }
+        }
+        let layer = env_layer();
+        unsafe { std::env::set_var("OLLAMA_CODE_MAX_TURNS", "many") };
+        let invalid = env_layer();
+        unsafe {
+            for (name, _) in vars {
+                std::env::remove_var(name);
+            }
+        }
+
+        let layer = layer.unwrap();
+        assert_eq!(layer.model.as_deref(), Some("qwen2.5-coder:7b"));
+        assert_eq!(layer.backend, Some(BackendKind::OpenAi));
+        assert_eq!(layer.max_turns, Some(4));
+        assert_eq!(layer.pull, Some(true));
+        let options = layer.options.unwrap();
+        assert_eq!(options.temperature, Some(0.2));
+        assert_eq!(options.stop, Some(vec!["<|im_end|>".into(), "</s>".into()]));
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            "Invalid value for OLLAMA_CODE_MAX_TURNS: 'many'"
+        );
+    }
+
+    #[test]
+    fn resolves_paths_against_the_config_file() {
+        let dir = std::env::temp_dir().join(format!("ollama_code-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+
+        std::fs::write(&path, "system_prompt_file = \"prompt.txt\"").unwrap();
+        let layer = read_layer(&path).unwrap().unwrap();
+        assert_eq!(layer.system_prompt_file, Some(dir.join("prompt.txt")));
+
+        std::fs::write(&path, "system_prompt_file = \"/etc/prompt.txt\"").unwrap();
+        let layer = read_layer(&path).unwrap().unwrap();
+        assert_eq!(
+            layer.system_prompt_file,
+            Some(PathBuf::from("/etc/prompt.txt"))
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn projects_cant_redirect_requests() {
+        let workspace =
+            std::env::temp_dir().join(format!("ollama_code-project-{}", std::process::id()));
+        let path = project_config_file(&workspace);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            "model = \"qwen2.5-coder:7b\"\n\
+             openai_url = \"https://example.com/v1\"\n\
+             api_key = \"stolen\"\n\
+             system_prompt_file = \"/home/user/.ssh/id_rsa\"\n",
+        )
+        .unwrap();
+
+        let mut warnings = Vec::new();
+        let layer = read_project_layer(&workspace, &mut warnings)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(layer.model.as_deref(), Some("qwen2.5-coder:7b"));
+        assert_eq!(layer.openai_url, None);
+        assert_eq!(layer.api_key, None);
+        assert_eq!(layer.system_prompt_file, None);
+        assert_eq!(
+            warnings,
+            [format!(
+                "ignoring openai_url, api_key, system_prompt_file in {}, set them in your user config or the environment instead",
+                path.display()
+            )]
+        );
+        std::fs::remove_dir_all(workspace).unwrap();
+    }
+
+    #[test]
+    fn sets_model_options() {
+        let mut options = ModelOptions::default();
+
+        options.set("temperature", "0.2").unwrap();
+        options.set("seed", " 42 ").unwrap();
+        options.set("stop", "<|im_end|>, </s>,").unwrap();
+        options.set("keep_alive", "10m").unwrap();
+        assert_eq!(options.temperature, 0.2);
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.stop, ["<|im_end|>", "</s>"]);
+        assert_eq!(options.keep_alive.as_deref(), Some("10m"));
+
+        options.set("seed", "unset").unwrap();
+        options.set("stop", "").unwrap();
+        options.set("keep_alive", "unset").unwrap();
+        assert_eq!(options.seed, None);
+        assert!(options.stop.is_empty());
+        assert_eq!(options.keep_alive, None);
+    }
+
+    #[test]
+    fn rejects_bad_model_options() {
+        let mut options = ModelOptions::default();
+
+        assert_eq!(
+            options.set("seed", "many"),
+            Err("Invalid value for seed: 'many'".into())
+        );
+        // Required options can't be unset
+        assert_eq!(
+            options.set("num_ctx", "unset"),
+            Err("Invalid value for num_ctx: 'unset'".into())
+        );
+        let unknown = options.set("temprature", "0.2").unwrap_err();
+        assert!(
+            unknown.starts_with("Unknown option 'temprature'. Available options: temperature,"),
+            "{}",
+            unknown
+        );
+        assert_eq!(options, ModelOptions::default());
+    }
+
+    #[test]
+    fn builds_request_options() {
+        let mut options = ModelOptions::default();
+        options.set("seed", "42").unwrap();
+        options.set("keep_alive", "10m").unwrap();
+
+        assert_eq!(
+            options.request_options(),
+            serde_json::json!({ "temperature": 0.5, "seed": 42, "num_ctx": 32768 })
+        );
+        assert_eq!(options.request_keep_alive(), Some(serde_json::json!("10m")));
+        // Plain numbers are seconds, and -1 keeps the model loaded
+        options.set("keep_alive", "-1").unwrap();
+        assert_eq!(options.request_keep_alive(), Some(serde_json::json!(-1)));
+        options.set("keep_alive", "unset").unwrap();
+        assert_eq!(options.request_keep_alive(), None);
+    }
+}
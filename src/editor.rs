@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use rustyline::{
+    Cmd, Completer, Config, Editor, EventHandler, Helper, Highlighter, Hinter, KeyCode, KeyEvent,
+    Modifiers,
+    error::ReadlineError,
+    history::FileHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+};
+
+const PROMPT: &str = "? Prompt : ";
+const CONTINUATION: char = '\\';
+
+/// Keeps asking for more input while the last line ends with a backslash,
+/// so long prompts can be spread over several lines.
+#[derive(Completer, Helper, Highlighter, Hinter)]
+struct PromptHelper;
+
+impl Validator for PromptHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if ctx.input().ends_with(CONTINUATION) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+pub struct LineEditor {
+    editor: Editor<PromptHelper, FileHistory>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new(workspace: &Path) -> Result<Self, ReadlineError> {
+        let config = Config::builder()
+            .max_history_size(1000)?
+            .history_ignore_dups(true)?
+            .history_ignore_space(true)
+            .bracketed_paste(true)
+            .build();
+
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(PromptHelper));
+        // Not every terminal reports Shift-Enter, Alt-Enter works as an alternative.
+        editor.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::SHIFT),
+            EventHandler::Simple(Cmd::Newline),
+        );
+        editor.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::ALT),
+            EventHandler::Simple(Cmd::Newline),
+        );
+
+        let history_path = history_file(workspace);
+        if let Some(ref path) = history_path {
+            // A missing history file just means this is the first session for the project
+            let _ = editor.load_history(path);
+        }
+
+        Ok(Self {
+            editor,
+            history_path,
+        })
+    }
+
+    /// Reads the next prompt. Lines continued with a trailing backslash are
+    /// joined with newlines.
+    pub fn read(&mut self) -> Result<String, ReadlineError> {
+        let input = self.editor.readline(PROMPT)?;
+        let input = input.replace(&format!("{}\n", CONTINUATION), "\n");
+
+        if !input.trim().is_empty() {
+            self.editor.add_history_entry(input.as_str())?;
+            self.save_history();
+        }
+
+        Ok(input)
+    }
+
+    fn save_history(&mut self) {
+        let Some(ref path) = self.history_path else {
+            return;
+        };
+        if let Some(parent) = path.parent()
+            && let Err(err) = std::fs::create_dir_all(parent)
+        {
+            tracing::warn!("Could not create history directory: {}", err);
+            return;
+        }
+        if let Err(err) = self.editor.save_history(path) {
+            tracing::warn!("Could not save history: {}", err);
+        }
+    }
+}
+
+/// History is kept per project, in the user's data directory, keyed by the
+/// absolute path of the workspace.
+fn history_file(workspace: &Path) -> Option<PathBuf> {
+    let workspace = std::fs::canonicalize(workspace).ok()?;
+    let key: String = workspace
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+
+    dirs::data_dir().map(|dir| dir.join("ollama_code").join("history").join(key))
+}
@@ -0,0 +1,461 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    assistant::{Assistant, TurnStats},
+    backend::{ChatBackend, RetryPolicy, Timeouts},
+    config::ModelOptions,
+    table,
+    tools::Toolchain,
+};
+
+/// One task of an eval suite, read from a TOML file:
+///
+/// ```toml
+/// name = "find-version"
+/// fixture = "fixtures/rust-project"
+/// prompt = "Which version is the package?"
+///
+/// [[checks]]
+/// type = "answer_contains"
+/// text = "0.3.1"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub name: String,
+    /// Directory the assistant works in. It's copied for every run, so
+    /// tasks can't affect each other.
+    pub fixture: PathBuf,
+    pub prompt: String,
+    pub max_turns: Option<usize>,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskFile {
+    name: Option<String>,
+    fixture: PathBuf,
+    prompt: String,
+    max_turns: Option<usize>,
+    #[serde(default)]
+    checks: Vec<Check>,
+}
+
+/// What a run has to get right to pass. Text is compared case-insensitively
+/// in answers, file contents have to match exactly.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Check {
+    AnswerContains { text: String },
+    AnswerExcludes { text: String },
+    FileContains { path: PathBuf, text: String },
+    FileEquals { path: PathBuf, content: String },
+}
+
+impl Check {
+    /// Checks the answer and the workspace after the run. Returns why the
+    /// check failed.
+    pub fn verify(&self, answer: &str, workspace: &Path) -> Result<(), String> {
+        let answer = answer.to_lowercase();
+        match self {
+            Check::AnswerContains { text } if !answer.contains(&text.to_lowercase()) => {
+                Err(format!("answer doesn't contain '{}'", text))
+            }
+            Check::AnswerExcludes { text } if answer.contains(&text.to_lowercase()) => {
+                Err(format!("answer contains '{}'", text))
+            }
+            Check::FileContains { path, text } => {
+                let content = read_file(workspace, path)?;
+                if content.contains(text.as_str()) {
+                    Ok(())
+                } else {
+                    Err(format!("{} doesn't contain '{}'", path.display(), text))
+                }
+            }
+            Check::FileEquals { path, content } => {
+                if read_file(workspace, path)? == *content {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} doesn't have the expected content",
+                        path.display()
+                    ))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads a file of the workspace. Checks can't look outside of it.
+fn read_file(workspace: &Path, path: &Path) -> Result<String, String> {
+    if !path
+        .components()
+        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!(
+            "{} is outside of the workspace, use a relative path",
+            path.display()
+        ));
+    }
+    fs::read_to_string(workspace.join(path))
+        .map_err(|err| format!("can't read {}: {}", path.display(), err))
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    Io(PathBuf, io::Error),
+    InvalidTask(PathBuf, String),
+    EmptySuite(PathBuf),
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            EvalError::InvalidTask(path, err) => {
+                write!(f, "Invalid task {}: {}", path.display(), err)
+            }
+            EvalError::EmptySuite(path) => write!(f, "No tasks found in {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Loads every `*.toml` file in the suite directory as a task, sorted by
+/// file name. Fixture paths are relative to the task file.
+pub fn load_suite(dir: &Path) -> Result<Vec<Task>, EvalError> {
+    let entries = fs::read_dir(dir).map_err(|err| EvalError::Io(dir.to_path_buf(), err))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|err| EvalError::Io(dir.to_path_buf(), err))?
+            .path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    if files.is_empty() {
+        return Err(EvalError::EmptySuite(dir.to_path_buf()));
+    }
+    files.iter().map(|path| load_task(path)).collect()
+}
+
+fn load_task(path: &Path) -> Result<Task, EvalError> {
+    let content = fs::read_to_string(path).map_err(|err| EvalError::Io(path.to_path_buf(), err))?;
+    let file: TaskFile = toml::from_str(&content)
+        .map_err(|err| EvalError::InvalidTask(path.to_path_buf(), err.to_string()))?;
+
+    let fixture = path.parent().unwrap_or(Path::new(".")).join(&file.fixture);
+    if !fixture.is_dir() {
+        return Err(EvalError::InvalidTask(
+            path.to_path_buf(),
+            format!("fixture {} is not a directory", fixture.display()),
+        ));
+    }
+    if file.checks.is_empty() {
+        return Err(EvalError::InvalidTask(
+            path.to_path_buf(),
+            "no checks, the task can't fail".into(),
+        ));
+    }
+
+    let name = file.name.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    Ok(Task {
+        name,
+        fixture,
+        prompt: file.prompt,
+        max_turns: file.max_turns,
+        checks: file.checks,
+    })
+}
+
+/// Settings shared by all runs of a suite
+#[derive(Debug, Clone)]
+pub struct EvalSettings {
+    pub options: ModelOptions,
+    pub retry: RetryPolicy,
+    pub timeouts: Timeouts,
+    pub max_turns: usize,
+    /// Replaces the built-in system prompt template
+    pub system_prompt: Option<String>,
+}
+
+/// The outcome of running one task with one model
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskResult {
+    pub task: String,
+    pub model: String,
+    pub passed: bool,
+    /// Failed checks, or the error that stopped the run
+    pub failures: Vec<String>,
+    pub answer: Option<String>,
+    pub stats: TurnStats,
+    pub latency_ms: u128,
+}
+
+impl TaskResult {
+    fn new(task: &Task, model: &str) -> Self {
+        Self {
+            task: task.name.clone(),
+            model: model.to_string(),
+            passed: false,
+            failures: Vec::new(),
+            answer: None,
+            stats: TurnStats::default(),
+            latency_ms: 0,
+        }
+    }
+
+    /// A task that couldn't run at all, e.g. because the model isn't
+    /// available. It still counts towards the model's summary.
+    pub fn failed(task: &Task, model: &str, reason: String) -> Self {
+        Self {
+            failures: vec![reason],
+            ..Self::new(task, model)
+        }
+    }
+}
+
+impl Display for TaskResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mark = if self.passed { "✅" } else { "❌" };
+        write!(
+            f,
+            "{} {} ({}): {} turns, {} tool calls, {:.1}s",
+            mark,
+            self.task,
+            self.model,
+            self.stats.requests,
+            self.stats.tool_calls,
+            self.latency_ms as f64 / 1000.0
+        )?;
+        for failure in &self.failures {
+            write!(f, "\n   - {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a task in a fresh copy of its fixture. Errors from the model or
+/// the fixture fail the task instead of stopping the suite.
+#[tracing::instrument(skip_all, fields(task = %task.name, model))]
+pub async fn run_task(
+    task: &Task,
+    model: &str,
+    backend: Box<dyn ChatBackend>,
+    native_tools: bool,
+    settings: &EvalSettings,
+) -> TaskResult {
+    let mut result = TaskResult::new(task, model);
+
+    let workspace = match Workspace::copy(&task.fixture) {
+        Ok(workspace) => workspace,
+        Err(err) => {
+            result
+                .failures
+                .push(format!("Could not copy fixture: {}", err));
+            return result;
+        }
+    };
+
+    let mut builder = Assistant::builder(model)
+        .with_options(settings.options.clone())
+        .with_backend(backend)
+        .with_retry_policy(settings.retry)
+        .with_timeouts(settings.timeouts)
+        .with_toolchain(Toolchain::new(workspace.path()))
+        .with_max_turns(task.max_turns.unwrap_or(settings.max_turns))
+        .with_native_tools(native_tools);
+    if let Some(template) = &settings.system_prompt {
+        builder = builder.with_system_prompt(template);
+    }
+    let mut assistant = builder.build();
+
+    let started = Instant::now();
+    let answer = assistant.ask(&task.prompt, &CancellationToken::new()).await;
+    result.latency_ms = started.elapsed().as_millis();
+    result.stats = assistant.stats().clone();
+
+    match answer {
+        Ok(answer) => {
+            result.failures = task
+                .checks
+                .iter()
+                .filter_map(|check| check.verify(&answer, workspace.path()).err())
+                .collect();
+            result.passed = result.failures.is_empty();
+            result.answer = Some(answer);
+        }
+        Err(err) => result.failures.push(err.to_string()),
+    }
+    result
+}
+
+/// A temporary copy of a fixture, removed when dropped
+struct Workspace {
+    path: PathBuf,
+}
+
+impl Workspace {
+    fn copy(fixture: &Path) -> io::Result<Self> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ollama_code-eval-{}-{}",
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let workspace = Self { path };
+        copy_dir(fixture, &workspace.path)?;
+        Ok(workspace)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Totals for one model over the whole suite
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelSummary {
+    pub model: String,
+    pub tasks: usize,
+    pub passed: usize,
+    pub success_rate: f64,
+    pub avg_turns: f64,
+    pub tool_calls: usize,
+    pub tool_errors: usize,
+    pub tool_error_rate: f64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub avg_latency_ms: f64,
+}
+
+impl ModelSummary {
+    fn from_results(model: &str, results: &[&TaskResult]) -> Self {
+        let tasks = results.len();
+        let passed = results.iter().filter(|r| r.passed).count();
+        let turns: usize = results.iter().map(|r| r.stats.requests).sum();
+        let tool_calls: usize = results.iter().map(|r| r.stats.tool_calls).sum();
+        let tool_errors: usize = results.iter().map(|r| r.stats.tool_errors).sum();
+        let latency: u128 = results.iter().map(|r| r.latency_ms).sum();
+
+        Self {
+            model: model.to_string(),
+            tasks,
+            passed,
+            success_rate: ratio(passed, tasks),
+            avg_turns: ratio(turns, tasks),
+            tool_calls,
+            tool_errors,
+            tool_error_rate: ratio(tool_errors, tool_calls),
+            prompt_tokens: results.iter().map(|r| r.stats.prompt_tokens).sum(),
+            completion_tokens: results.iter().map(|r| r.stats.completion_tokens).sum(),
+            avg_latency_ms: ratio(latency as usize, tasks),
+        }
+    }
+}
+
+fn ratio(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// All results of a suite, with a summary per model
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub summaries: Vec<ModelSummary>,
+    pub results: Vec<TaskResult>,
+}
+
+impl Report {
+    /// Summarizes the results, keeping the models in the order they ran
+    pub fn new(results: Vec<TaskResult>) -> Self {
+        let mut models: Vec<&str> = Vec::new();
+        for result in &results {
+            if !models.contains(&result.model.as_str()) {
+                models.push(&result.model);
+            }
+        }
+        let summaries = models
+            .iter()
+            .map(|model| {
+                let runs: Vec<&TaskResult> = results.iter().filter(|r| r.model == *model).collect();
+                ModelSummary::from_results(model, &runs)
+            })
+            .collect();
+
+        Self { summaries, results }
+    }
+
+    /// Renders the summaries as a table for the terminal
+    pub fn table(&self) -> String {
+        let rows: Vec<[String; 7]> = self
+            .summaries
+            .iter()
+            .map(|s| {
+                [
+                    s.model.clone(),
+                    format!("{}/{} ({:.0}%)", s.passed, s.tasks, s.success_rate * 100.0),
+                    format!("{:.1}", s.avg_turns),
+                    s.tool_calls.to_string(),
+                    format!("{} ({:.0}%)", s.tool_errors, s.tool_error_rate * 100.0),
+                    format!("{}/{}", s.prompt_tokens, s.completion_tokens),
+                    format!("{:.1}s", s.avg_latency_ms / 1000.0),
+                ]
+            })
+            .collect();
+
+        table::render(
+            [
+                "MODEL",
+                "PASSED",
+                "TURNS",
+                "TOOL CALLS",
+                "TOOL ERRORS",
+                "TOKENS IN/OUT",
+                "LATENCY",
+            ],
+            &rows,
+        )
+    }
+}
@@ -0,0 +1,190 @@
+use std::{io::Write, sync::Mutex, time::Duration};
+
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::assistant::TurnStats;
+
+/// Something that happened while the assistant worked on a question. Every
+/// `ToolCallStarted` is followed by a `ToolCallFinished` for the same call.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AssistantEvent {
+    TurnStarted {
+        question: String,
+    },
+    /// A request to the model. `turn` counts from 1, the final request when
+    /// wrapping up doesn't offer tools.
+    ModelRequest {
+        turn: usize,
+        with_tools: bool,
+    },
+    /// A piece of the model's answer as it streams in
+    TokenDelta {
+        content: String,
+    },
+    ToolCallStarted {
+        id: Option<String>,
+        name: String,
+        arguments: Value,
+    },
+    /// `result` is what the model gets to see, also when the call failed
+    ToolCallFinished {
+        id: Option<String>,
+        name: String,
+        result: String,
+        #[serde(rename = "duration_ms", serialize_with = "milliseconds")]
+        duration: Duration,
+        error: Option<String>,
+    },
+    /// The call was made before with the same arguments and isn't run again
+    ToolCallRepeated {
+        id: Option<String>,
+        name: String,
+    },
+    /// The request to the model failed for a reason that might go away and
+    /// is sent again after `delay`. Content streamed before the failure is
+    /// void.
+    Retrying {
+        retry: u32,
+        max_retries: u32,
+        #[serde(rename = "delay_ms", serialize_with = "milliseconds")]
+        delay: Duration,
+        error: String,
+    },
+    /// The model has to answer with what it has, without further tools
+    WrappingUp {
+        reason: WrapUpReason,
+    },
+    /// Sent at the end of every question, also when it failed or was
+    /// cancelled
+    TurnFinished {
+        usage: TurnStats,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum WrapUpReason {
+    TurnLimit { turns: usize },
+    Looping,
+}
+
+fn milliseconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// Receives the events of an assistant. Handlers are called in the order
+/// they were added, on the task running the question, so they should be
+/// quick. Use `channel` to process events elsewhere.
+pub type EventHandler = Box<dyn Fn(&AssistantEvent) + Send + Sync>;
+
+/// A handler that sends the events to the returned receiver
+pub fn channel() -> (EventHandler, mpsc::UnboundedReceiver<AssistantEvent>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let handler = Box::new(move |event: &AssistantEvent| {
+        // Nobody listening anymore is fine
+        let _ = sender.send(event.clone());
+    });
+    (handler, receiver)
+}
+
+/// Prints the progress of tool calls to stdout, the way the REPL shows it
+pub fn console() -> EventHandler {
+    Box::new(|event| {
+        if let Some(line) = console_line(event) {
+            println!("{}", line);
+        }
+    })
+}
+
+fn console_line(event: &AssistantEvent) -> Option<String> {
+    match event {
+        AssistantEvent::ToolCallStarted {
+            name, arguments, ..
+        } => Some(describe_call(name, arguments)),
+        AssistantEvent::ToolCallFinished {
+            name,
+            result,
+            error: None,
+            ..
+        } => describe_result(name, result),
+        AssistantEvent::ToolCallFinished {
+            error: Some(error), ..
+        } => Some(format!("   ❌ {}", error)),
+        AssistantEvent::ToolCallRepeated { name, .. } => Some(format!(
+            "   ♻️  Repeated call to {}, reusing the earlier result",
+            name
+        )),
+        AssistantEvent::Retrying {
+            retry,
+            max_retries,
+            delay,
+            error,
+        } => Some(format!(
+            "⚠️  {}. Retrying in {:.1}s ({}/{})",
+            error,
+            delay.as_secs_f64(),
+            retry,
+            max_retries
+        )),
+        AssistantEvent::WrappingUp {
+            reason: WrapUpReason::TurnLimit { turns },
+        } => Some(format!(
+            "⏳ Reached the limit of {} turns, asking for a final answer",
+            turns
+        )),
+        AssistantEvent::WrappingUp {
+            reason: WrapUpReason::Looping,
+        } => Some(
+            "🔁 The model keeps repeating the same tool calls, asking for a final answer".into(),
+        ),
+        _ => None,
+    }
+}
+
+/// Progress message for a tool call. The built-in tools get their own,
+/// other tools a generic one.
+fn describe_call(name: &str, arguments: &Value) -> String {
+    let string = |key: &str| arguments[key].as_str().unwrap_or_default().to_string();
+    match name {
+        "list_directory" => format!("📁 Listing directory: {}", string("path")),
+        "read_file" => format!("📄 Reading file: {}", string("path")),
+        "pwd" => "📍 Getting current directory...".to_string(),
+        "grep" => format!(
+            "🔍 Searching for '{}' in {}",
+            string("search_pattern"),
+            string("path")
+        ),
+        _ => format!("🔧 Calling {}", name),
+    }
+}
+
+fn describe_result(name: &str, output: &str) -> Option<String> {
+    match name {
+        "list_directory" => Some(format!("   Found {} items", output.lines().count())),
+        "read_file" => Some(format!("   Read {} characters", output.len())),
+        "pwd" => Some(format!("   Current directory: {}", output.trim())),
+        "grep" => Some("   Search completed".to_string()),
+        _ => None,
+    }
+}
+
+/// Writes every event as a line of JSON, e.g. to a file for other tools to
+/// follow
+pub fn json_lines(writer: impl Write + Send + 'static) -> EventHandler {
+    let writer = Mutex::new(writer);
+    Box::new(move |event| {
+        let Ok(mut writer) = writer.lock() else {
+            return;
+        };
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(writer))
+            .and_then(|_| writer.flush());
+        if let Err(err) = written {
+            tracing::warn!("Could not write event: {}", err);
+        }
+    })
+}
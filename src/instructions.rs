@@ -0,0 +1,54 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::constants::INSTRUCTION_FILES;
+
+/// Collects project and user instructions to append to the system prompt.
+///
+/// The user-level file comes first, followed by project files from the
+/// outermost parent directory down to the workspace root, so the most
+/// specific instructions are read last.
+pub fn load(workspace: &Path) -> Option<String> {
+    let mut files = Vec::new();
+
+    if let Some(file) = dirs::config_dir().and_then(|dir| find_in(&dir.join("ollama_code"))) {
+        files.push(file);
+    }
+
+    let workspace = fs::canonicalize(workspace).ok()?;
+    let mut project_files: Vec<PathBuf> = workspace.ancestors().filter_map(find_in).collect();
+    project_files.reverse();
+    files.extend(project_files);
+
+    let sections: Vec<String> = files
+        .iter()
+        .filter_map(|path| match fs::read_to_string(path) {
+            Ok(contents) if !contents.trim().is_empty() => Some(format!(
+                "Instructions from {}:\n\n{}",
+                path.display(),
+                contents.trim()
+            )),
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!("Could not read {}: {}", path.display(), err);
+                None
+            }
+        })
+        .collect();
+
+    if sections.is_empty() {
+        None
+    } else {
+        tracing::info!("Loaded instructions from {:?}", files);
+        Some(sections.join("\n\n"))
+    }
+}
+
+fn find_in(dir: &Path) -> Option<PathBuf> {
+    INSTRUCTION_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
@@ -0,0 +1,52 @@
+//! A coding assistant for local models. The `ollama_code` binary is one
+//! front end, the same agent loop can be embedded in other programs:
+//!
+//! ```no_run
+//! use ollama_code::{Assistant, OllamaClient, Toolchain};
+//! use tokio_util::sync::CancellationToken;
+//!
+//! # async fn run() -> Result<(), ollama_code::AssistantError> {
+//! let mut assistant = Assistant::builder("llama3.1:8b")
+//!     .with_backend(Box::new(OllamaClient::new("http://localhost:11434")))
+//!     .with_toolchain(Toolchain::new("path/to/project"))
+//!     .build();
+//!
+//! let answer = assistant
+//!     .ask("What does this project do?", &CancellationToken::new())
+//!     .await?;
+//! println!("{}", answer);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Custom tools implement [`Tool`] and are added with
+//! [`AssistantBuilder::with_tool`].
+
+pub mod assistant;
+pub mod backend;
+pub mod config;
+mod constants;
+pub mod eval;
+pub mod events;
+pub mod instructions;
+pub mod mcp;
+pub mod models;
+pub mod ollama;
+pub mod openai;
+pub mod prompts;
+pub mod recording;
+mod repetition;
+mod schema;
+pub mod server;
+pub mod session;
+mod table;
+mod tool_parser;
+pub mod tools;
+
+pub use assistant::{Assistant, AssistantBuilder, AssistantError, TurnStats};
+pub use backend::{BackendKind, ChatBackend};
+pub use config::ModelOptions;
+pub use events::AssistantEvent;
+pub use ollama::{OllamaClient, ToolDefinition};
+pub use openai::OpenAiClient;
+pub use tools::{Tool, Toolchain};
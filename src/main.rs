@@ -1,47 +1,285 @@
-use std::process::{self, exit};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
 
-use clap::Parser;
-use cli_prompts::{DisplayPrompt, prompts::Input};
+use clap::{Args, Parser, Subcommand};
 use color_eyre::Result;
+use rustyline::error::ReadlineError;
 use serde_json::Value;
+use tokio_util::sync::CancellationToken;
 use tracing::Level;
 
-use crate::assistant::Assistant;
-mod assistant;
-mod constants;
-mod ollama;
+use ollama_code::{
+    Tool,
+    assistant::{Assistant, AssistantBuilder, AssistantError},
+    backend::{BackendKind, ChatBackend},
+    config::{self, Config, ConfigLayer, OptionsLayer, TrustedServers},
+    eval::{self, EvalSettings, Report, TaskResult},
+    events, instructions,
+    mcp::{self, McpServerConfig, McpTool},
+    models::{self, ModelError},
+    ollama::OllamaClient,
+    openai::OpenAiClient,
+    recording::{Recorder, Replayer},
+    server::{self, AssistantFactory},
+    session::Session,
+    tools::{ToolSet, Toolchain},
+};
+
+use crate::{editor::LineEditor, markdown::MarkdownRenderer};
+
+mod editor;
+mod markdown;
 mod otel;
-mod tools;
+mod pull;
 
 #[derive(Parser)]
 struct CliArgs {
-    ///Which model to use
-    #[arg(short, long, default_value = "llama3.1:8b")]
-    pub model: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    ///Which model to use [default: llama3.1:8b]
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    ///Which API to talk to [default: ollama]
+    #[arg(long)]
+    pub backend: Option<BackendKind>,
 
     ///Sets the path to operate in.
     #[arg(short, long, default_value = ".")]
     pub path: String,
+
+    ///Reads the system prompt template from this file instead of using the built-in one for the model.
+    ///Supports {{workspace_root}}, {{os}}, {{date}}, {{model}} and {{tools}} placeholders.
+    #[arg(long)]
+    pub system_prompt_file: Option<PathBuf>,
+
+    ///Disables colored output. Also respects the NO_COLOR environment variable.
+    #[arg(long)]
+    pub no_color: bool,
+
+    ///Pulls the model without asking if it isn't installed
+    #[arg(long)]
+    pub pull: bool,
+
+    ///Records every request to the model and its response to this file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    ///Answers with the responses recorded in this file instead of asking the model
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    ///Writes every assistant event, such as model requests and tool calls, to this file as JSON Lines
+    #[arg(long, value_name = "FILE")]
+    pub events: Option<PathBuf>,
+
+    ///How many model requests a question may take before the model has to answer [default: 10]
+    #[arg(long)]
+    pub max_turns: Option<usize>,
+
+    #[command(flatten)]
+    pub options: OptionsArgs,
+}
+
+// Model options. These can also be changed while running with /set <option> <value>.
+#[derive(Args)]
+#[command(next_help_heading = "Model options")]
+struct OptionsArgs {
+    ///Sampling temperature [default: 0.5]
+    #[arg(long)]
+    pub temperature: Option<f64>,
+
+    ///Nucleus sampling probability mass
+    #[arg(long)]
+    pub top_p: Option<f64>,
+
+    ///Only sample from the k most likely tokens
+    #[arg(long)]
+    pub top_k: Option<u64>,
+
+    ///Random seed, for reproducible runs
+    #[arg(long)]
+    pub seed: Option<i64>,
+
+    ///Context window size in tokens [default: 32768]
+    #[arg(long)]
+    pub num_ctx: Option<u64>,
+
+    ///Maximum number of tokens to generate per response
+    #[arg(long)]
+    pub num_predict: Option<i64>,
+
+    ///Penalty for repeated tokens
+    #[arg(long)]
+    pub repeat_penalty: Option<f64>,
+
+    ///Stop sequence. Can be given multiple times.
+    #[arg(long)]
+    pub stop: Vec<String>,
+
+    ///How long the model stays loaded after a request, e.g. 5m, 1h or -1 for forever
+    #[arg(long)]
+    pub keep_alive: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    ///Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    ///Lists the installed models and whether they support tool calling
+    Models,
+    ///Speaks JSON-RPC, for driving the assistant from an editor
+    Serve {
+        ///Uses stdin and stdout, the only transport so far
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+    ///Offers the built-in tools to other programs as an MCP server over stdin and stdout
+    McpServe,
+    ///Runs a suite of tasks against one or more models and reports how well they did
+    Eval {
+        ///Directory with the task files
+        #[arg(default_value = "evals")]
+        suite: PathBuf,
+
+        ///Models to evaluate, comma separated [default: the configured model]
+        #[arg(long, value_delimiter = ',')]
+        models: Vec<String>,
+
+        ///Also writes the full report as JSON to this file
+        #[arg(long, value_name = "FILE")]
+        json: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    ///Prints the effective configuration and where each value came from
+    Show,
+}
+
+impl CliArgs {
+    fn config_layer(&self) -> ConfigLayer {
+        ConfigLayer {
+            model: self.model.clone(),
+            backend: self.backend,
+            system_prompt_file: self.system_prompt_file.clone(),
+            color: self.no_color.then_some(false),
+            pull: self.pull.then_some(true),
+            max_turns: self.max_turns,
+            options: Some(OptionsLayer {
+                temperature: self.options.temperature,
+                top_p: self.options.top_p,
+                top_k: self.options.top_k,
+                seed: self.options.seed,
+                num_ctx: self.options.num_ctx,
+                num_predict: self.options.num_predict,
+                repeat_penalty: self.options.repeat_penalty,
+                stop: (!self.options.stop.is_empty()).then(|| self.options.stop.clone()),
+                keep_alive: self.options.keep_alive.clone(),
+            }),
+            ..Default::default()
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
+    let args = CliArgs::parse();
+    let config = Config::load(Path::new(&args.path), args.config_layer())?;
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let ollama = OllamaClient::new(&config.ollama_url.value);
+    let openai = OpenAiClient::new(&config.openai_url.value, config.api_key.value.clone());
+
+    match args.command {
+        Some(Command::Config {
+            command: ConfigCommand::Show,
+        }) => {
+            println!("{}", config.show());
+            return Ok(());
+        }
+        Some(Command::Models) => {
+            match config.backend.value {
+                BackendKind::Ollama => println!("{}", models::table(&models::list(&ollama).await?)),
+                BackendKind::OpenAi => println!("{}", openai.models().await?.join("\n")),
+            }
+            return Ok(());
+        }
+        Some(Command::Eval {
+            suite,
+            models,
+            json,
+        }) => {
+            return run_eval(&config, &suite, models, json.as_deref()).await;
+        }
+        Some(Command::Serve { .. }) => {
+            return serve_stdio(Path::new(&args.path), config, ollama, openai).await;
+        }
+        Some(Command::McpServe) => {
+            let mut tools = ToolSet::default();
+            for tool in Toolchain::new(&args.path).tools() {
+                tools.add(tool);
+            }
+            mcp::serve(&tools, tokio::io::stdin(), tokio::io::stdout()).await?;
+            return Ok(());
+        }
+        None => {}
+    }
+
     let _tracer =
-        otel::setup_otlp("http://localhost:4317", "ollama_code").expect("Failed to setup OTLP");
+        otel::setup_otlp(&config.otlp_endpoint.value, "ollama_code").expect("Failed to setup OTLP");
 
     let span = tracing::span!(Level::INFO, "root");
     let _guard = span.enter();
 
-    let args = CliArgs::parse();
-
-    if let Err(err) = ollama::check_available(&args.model).await {
-        println!("ollama unavailable: {}", err);
-        exit(1);
-    }
+    let (backend, native_tools): (Box<dyn ChatBackend>, bool) = if let Some(path) = &args.replay {
+        // Replays don't need a server, so there's no model to check
+        let replayer = Replayer::load(path)?;
+        println!("Replaying {}", path.display());
+        let native_tools = replayer.native_tools();
+        (Box::new(replayer), native_tools)
+    } else {
+        match config.backend.value {
+            BackendKind::Ollama => {
+                let native_tools = check_ollama_model(&config, &ollama).await;
+                (Box::new(ollama), native_tools)
+            }
+            BackendKind::OpenAi => {
+                let native_tools = check_openai_model(&config, &openai).await;
+                (Box::new(openai), native_tools)
+            }
+        }
+    };
+    let backend: Box<dyn ChatBackend> = match &args.record {
+        Some(path) => {
+            println!("Recording model interactions to {}", path.display());
+            Box::new(Recorder::new(backend, path)?)
+        }
+        None => backend,
+    };
 
-    repl(args).await;
+    repl(
+        Path::new(&args.path),
+        config,
+        backend,
+        native_tools,
+        args.events.as_deref(),
+    )
+    .await?;
     opentelemetry_sdk::trace::SdkTracerProvider::builder()
         .build()
         .shutdown()?;
@@ -49,34 +287,503 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn repl(args: CliArgs) {
-    println!("Let's get started. Press [ESC] to exit.");
-    let mut assistant = Assistant::new(args.model).with_progress_callback(Box::new(|msg| {
-        println!("{}", msg);
-    }));
+/// Makes sure the model is installed, offering to pull it if it isn't.
+/// Returns whether the model supports native tool calling.
+async fn check_ollama_model(config: &Config, client: &OllamaClient) -> bool {
+    let mut check = models::check(client, &config.model.value).await;
+    if let Err(err @ ModelError::NotInstalled { .. }) = &check
+        && confirm_pull(config, err)
+    {
+        if let Err(err) = pull::pull(client, &config.model.value).await {
+            println!("Could not pull {}: {}", config.model.value, err);
+            exit(1);
+        }
+        check = models::check(client, &config.model.value).await;
+    }
+
+    match check {
+        Ok(info) => {
+            println!("Using {}", info);
+            if !info.supports_tools() {
+                println!(
+                    "Warning: {} does not support native tool calling, tool calls are parsed from its answers instead",
+                    info.name
+                );
+                let suggestions = models::suggestions(client).await;
+                if !suggestions.is_empty() {
+                    println!(
+                        "Installed models with tool support: {}",
+                        suggestions.join(", ")
+                    );
+                }
+            }
+            if let Some(context_length) = info.context_length
+                && config.num_ctx.value > context_length
+            {
+                println!(
+                    "Warning: num_ctx is {} but the model only supports {} tokens",
+                    config.num_ctx.value, context_length
+                );
+            }
+            info.supports_tools()
+        }
+        Err(err) => {
+            println!("{}", err);
+            exit(1);
+        }
+    }
+}
+
+/// OpenAI compatible servers don't tell whether a model supports tools, so
+/// that's assumed. A model missing from the list is only a warning, some
+/// servers answer with whatever model they have loaded.
+async fn check_openai_model(config: &Config, client: &OpenAiClient) -> bool {
+    match client.models().await {
+        Ok(models) => {
+            if !models.is_empty() && !models.contains(&config.model.value) {
+                println!(
+                    "Warning: {} doesn't list model '{}'. Available models: {}",
+                    client.base_url(),
+                    config.model.value,
+                    models.join(", ")
+                );
+            }
+            println!("Using {} via {}", config.model.value, client.describe());
+            true
+        }
+        Err(err) => {
+            println!("{} unavailable: {}", client.describe(), err);
+            exit(1);
+        }
+    }
+}
+
+/// Runs every task of the suite with each model, one after another, so
+/// latencies aren't skewed by models competing for the GPU.
+async fn run_eval(
+    config: &Config,
+    suite: &Path,
+    models: Vec<String>,
+    json: Option<&Path>,
+) -> Result<()> {
+    let tasks = eval::load_suite(suite)?;
+    let models = if models.is_empty() {
+        vec![config.model.value.clone()]
+    } else {
+        models
+    };
+    let settings = EvalSettings {
+        options: config.model_options(),
+        retry: config.retry_policy(),
+        timeouts: config.timeouts(),
+        max_turns: config.max_turns.value,
+        system_prompt: config.system_prompt()?,
+    };
+    let ollama = OllamaClient::new(&config.ollama_url.value);
+    let openai = OpenAiClient::new(&config.openai_url.value, config.api_key.value.clone());
+
+    let mut results = Vec::new();
+    for model in &models {
+        let native_tools = match config.backend.value {
+            BackendKind::Ollama => match models::check(&ollama, model).await {
+                Ok(info) => info.supports_tools(),
+                Err(err) => {
+                    println!("Failing every task for {}: {}", model, err);
+                    results.extend(
+                        tasks
+                            .iter()
+                            .map(|task| TaskResult::failed(task, model, err.to_string())),
+                    );
+                    continue;
+                }
+            },
+            BackendKind::OpenAi => true,
+        };
+        println!(
+            "Evaluating {} on {} tasks from {}",
+            model,
+            tasks.len(),
+            suite.display()
+        );
+
+        for task in &tasks {
+            let backend: Box<dyn ChatBackend> = match config.backend.value {
+                BackendKind::Ollama => Box::new(ollama.clone()),
+                BackendKind::OpenAi => Box::new(openai.clone()),
+            };
+            let result = eval::run_task(task, model, backend, native_tools, &settings).await;
+            println!("{}", result);
+            results.push(result);
+        }
+    }
+
+    let report = Report::new(results);
+    println!("\n{}", report.table());
+    if let Some(path) = json {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        println!("Report written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Pulls without asking when configured to. Otherwise asks, but only if
+/// there is someone at the terminal to answer.
+fn confirm_pull(config: &Config, err: &ModelError) -> bool {
+    if config.pull.value {
+        return true;
+    }
+    if !is_interactive() {
+        return false;
+    }
+
+    println!("{}", err);
+    confirm(&format!("Pull {} now?", config.model.value))
+}
+
+fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+async fn repl(
+    workspace: &Path,
+    config: Config,
+    backend: Box<dyn ChatBackend>,
+    native_tools: bool,
+    events_file: Option<&Path>,
+) -> Result<()> {
+    println!("Let's get started. Press [Ctrl-D] or [Ctrl-C] to exit.");
+    println!("End a line with \\ or press [Shift-Enter] to continue on the next line.");
+    let mut editor = LineEditor::new(workspace)?;
+    let color = config.color.value
+        && std::env::var_os("NO_COLOR").is_none()
+        && std::io::stdout().is_terminal();
+    let renderer = MarkdownRenderer::new(color);
+    let system_prompt = config.system_prompt()?;
+    let mcp_tools = mcp_tools(workspace, &config, is_interactive()).await;
+    let mut builder = assistant_builder(
+        &config,
+        workspace,
+        backend,
+        native_tools,
+        system_prompt.as_deref(),
+        &mcp_tools,
+    )
+    .with_event_handler(events::console());
+    if let Some(path) = events_file {
+        builder = builder.with_event_handler(events::json_lines(File::create(path)?));
+        println!("Events are written to {}", path.display());
+    }
+    if is_interactive() {
+        let max_turns = config.max_turns.value;
+        builder = builder.with_turn_limit_callback(Box::new(move |turns| {
+            let question = format!("No answer after {} turns. Allow {} more?", turns, max_turns);
+            // Reading the answer blocks, which mustn't hold up the runtime
+            Box::pin(async move {
+                let allowed = tokio::task::spawn_blocking(move || confirm(&question))
+                    .await
+                    .unwrap_or(false);
+                if allowed { max_turns } else { 0 }
+            })
+        }));
+    }
+    if is_interactive() {
+        builder = builder.with_approval_callback(Box::new(|call| {
+            let question = format!(
+                "   Allow {} with {}?",
+                call.function.name, call.function.arguments
+            );
+            Box::pin(async move {
+                tokio::task::spawn_blocking(move || confirm(&question))
+                    .await
+                    .unwrap_or(false)
+            })
+        }));
+    }
+    let mut assistant = builder.build();
+    let mut session = Session::new(workspace, &config.model.value, assistant.options());
+    if let Some(path) = session.path() {
+        println!("Session is recorded to {}", path.display());
+    }
 
     loop {
-        let input_prompt = Input::new("Prompt ", |s| Ok(s.to_string()));
-        let question = match input_prompt.display() {
+        let question = match editor.read() {
             Ok(val) => val,
-            Err(_) => process::exit(0),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Ok(()),
+            Err(err) => return Err(err.into()),
         };
+        if question.trim().is_empty() {
+            continue;
+        }
+        if let Some(setting) = command_arguments(&question, "/set") {
+            set_option(&mut assistant, &mut session, setting);
+            continue;
+        }
+
+        // Ctrl-C while the assistant is working cancels the turn and brings
+        // the prompt back. At the prompt itself, Ctrl-C exits.
+        let cancel = CancellationToken::new();
+        let ctrl_c = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    cancel.cancel();
+                }
+            }
+        });
+        let result = assistant.ask(&question, &cancel).await;
+        ctrl_c.abort();
 
-        match assistant.ask(&question).await {
+        match result {
             Ok(response) => {
                 // Try to parse as JSON first, fallback to plain text
                 if let Ok(json_val) = serde_json::from_str::<Value>(&response) {
                     if let Some(content) = json_val.get("content").and_then(|c| c.as_str()) {
-                        println!("\n{}\n", content);
+                        println!("\n{}\n", renderer.render(content));
                     } else {
-                        println!("\n{}\n", response);
+                        println!("\n{}\n", renderer.render(&response));
                     }
                 } else {
                     // Plain text response
-                    println!("\n{}\n", response);
+                    println!("\n{}\n", renderer.render(&response));
                 }
             }
+            Err(AssistantError::Cancelled) => {
+                println!("\nCancelled. Press [Ctrl-C] again to exit.\n")
+            }
             Err(err) => eprintln!("[ERR]: {}", err),
         }
+
+        session.messages = assistant.conversation().to_vec();
+        if let Err(err) = session.save() {
+            tracing::warn!("Could not save session: {}", err);
+        }
+    }
+}
+
+/// Everything the REPL and `serve` have in common: the model, backend,
+/// settings, tools and instructions
+fn assistant_builder(
+    config: &Config,
+    workspace: &Path,
+    backend: Box<dyn ChatBackend>,
+    native_tools: bool,
+    system_prompt: Option<&str>,
+    mcp_tools: &[McpTool],
+) -> AssistantBuilder {
+    let mut builder = Assistant::builder(&config.model.value)
+        .with_options(config.model_options())
+        .with_backend(backend)
+        .with_retry_policy(config.retry_policy())
+        .with_timeouts(config.timeouts())
+        .with_toolchain(Toolchain::new(workspace))
+        .with_max_turns(config.max_turns.value)
+        .with_native_tools(native_tools);
+    if let Some(system_prompt) = system_prompt {
+        builder = builder.with_system_prompt(system_prompt);
+    }
+    for tool in mcp_tools {
+        builder = builder.with_tool(tool.clone());
+    }
+    if let Some(instructions) = instructions::load(workspace) {
+        builder = builder.with_instructions(instructions);
+    }
+    builder
+}
+
+/// Starts the configured MCP servers and returns their tools. A server that
+/// doesn't start is reported, the assistant works without it. Reports go
+/// to stderr, which keeps stdout free for `serve`.
+async fn mcp_tools(workspace: &Path, config: &Config, interactive: bool) -> Vec<McpTool> {
+    let mut taken: Vec<String> = Toolchain::new(workspace)
+        .definitions()
+        .into_iter()
+        .map(|definition| definition.function.name)
+        .collect();
+    let mut servers = config.mcp_servers();
+    for name in untrusted_mcp_servers(workspace, config, &servers, interactive) {
+        servers.remove(&name);
+    }
+
+    let mut tools = Vec::new();
+    for result in mcp::start_all(&servers).await {
+        let server = match result {
+            Ok(server) => server,
+            Err((name, err)) => {
+                eprintln!("[ERR]: MCP server {} failed to start: {}", name, err);
+                continue;
+            }
+        };
+        let mut added = 0;
+        for tool in server.tools() {
+            let name = tool.definition().function.name;
+            if taken.contains(&name) {
+                eprintln!(
+                    "[ERR]: Skipping {} from MCP server {}, there already is a tool with that name",
+                    name,
+                    server.name()
+                );
+                continue;
+            }
+            taken.push(name);
+            tools.push(tool.clone());
+            added += 1;
+        }
+        eprintln!("Using {} tools from MCP server {}", added, server.name());
+    }
+    tools
+}
+
+/// Servers from the project config run whatever the repository says, so
+/// they need the user's consent first. The answer is remembered for the
+/// workspace. Without anyone to ask, servers that weren't allowed before
+/// don't start. Returns the names of the servers not to start.
+fn untrusted_mcp_servers(
+    workspace: &Path,
+    config: &Config,
+    servers: &BTreeMap<String, McpServerConfig>,
+    interactive: bool,
+) -> Vec<String> {
+    let mut trusted = TrustedServers::load();
+    let untrusted: Vec<(&String, &McpServerConfig)> = servers
+        .iter()
+        .filter(|(name, server)| {
+            server.enabled
+                && config.is_project_mcp_server(workspace, name)
+                && !trusted.is_trusted(workspace, name, server)
+        })
+        .collect();
+    if untrusted.is_empty() {
+        return Vec::new();
+    }
+
+    let project_config = config::project_config_file(workspace);
+    if interactive {
+        println!(
+            "The project config {} starts these MCP servers:",
+            project_config.display()
+        );
+        for (name, server) in &untrusted {
+            println!("   {}: {}", name, server.command_line());
+        }
+        if confirm("Start them, now and in the future?") {
+            for (name, server) in &untrusted {
+                trusted.trust(workspace, name, server);
+            }
+            if let Err(err) = trusted.save() {
+                eprintln!("[ERR]: Could not remember the MCP servers: {}", err);
+            }
+            return Vec::new();
+        }
+    }
+    for (name, server) in &untrusted {
+        eprintln!(
+            "Not starting MCP server {} ({}) from {}. Run ollama_code in {} to allow it.",
+            name,
+            server.command_line(),
+            project_config.display(),
+            workspace.display()
+        );
+    }
+    untrusted
+        .into_iter()
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Editors start this as a subprocess and read its stdout, so nothing but
+/// protocol messages may go there.
+async fn serve_stdio(
+    workspace: &Path,
+    config: Config,
+    ollama: OllamaClient,
+    openai: OpenAiClient,
+) -> Result<()> {
+    let native_tools = match config.backend.value {
+        BackendKind::Ollama => match models::check(&ollama, &config.model.value).await {
+            Ok(info) => info.supports_tools(),
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        },
+        BackendKind::OpenAi => true,
+    };
+    let system_prompt = config.system_prompt()?;
+    let mcp_tools = mcp_tools(workspace, &config, false).await;
+    eprintln!("Serving {} over stdio", config.model.value);
+
+    let factory: AssistantFactory = Box::new(move |workspace| {
+        let backend: Box<dyn ChatBackend> = match config.backend.value {
+            BackendKind::Ollama => Box::new(ollama.clone()),
+            BackendKind::OpenAi => Box::new(openai.clone()),
+        };
+        assistant_builder(
+            &config,
+            workspace,
+            backend,
+            native_tools,
+            system_prompt.as_deref(),
+            &mcp_tools,
+        )
+    });
+    server::serve(factory, workspace, tokio::io::stdin(), tokio::io::stdout()).await?;
+    Ok(())
+}
+
+/// The arguments of a slash command, if the line is that command. `/set`
+/// doesn't match `/settings`.
+fn command_arguments<'a>(line: &'a str, command: &str) -> Option<&'a str> {
+    let arguments = line.trim().strip_prefix(command)?;
+    (arguments.is_empty() || arguments.starts_with(char::is_whitespace)).then_some(arguments)
+}
+
+/// Handles `/set <option> <value>`. Without arguments, prints the current options.
+fn set_option(assistant: &mut Assistant, session: &mut Session, setting: &str) {
+    let mut parts = setting.trim().splitn(2, char::is_whitespace);
+    let Some(key) = parts.next().filter(|key| !key.is_empty()) else {
+        println!("{}", assistant.options());
+        return;
+    };
+    let value = parts.next().unwrap_or_default();
+
+    match assistant.set_option(key, value) {
+        Ok(()) => {
+            session.record_option_change(key, value);
+            if let Err(err) = session.save() {
+                tracing::warn!("Could not save session: {}", err);
+            }
+            println!("{} updated", key);
+        }
+        Err(err) => eprintln!("[ERR]: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_slash_commands() {
+        assert_eq!(command_arguments("/set", "/set"), Some(""));
+        assert_eq!(
+            command_arguments("  /set seed 42 ", "/set"),
+            Some(" seed 42")
+        );
+        assert_eq!(
+            command_arguments("/set\tseed 42", "/set"),
+            Some("\tseed 42")
+        );
+        assert_eq!(command_arguments("/settings", "/set"), None);
+        assert_eq!(command_arguments("What does /set do?", "/set"), None);
     }
 }
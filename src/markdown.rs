@@ -0,0 +1,623 @@
+use crossterm::style::{Attribute, Color, ContentStyle, StyledContent};
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+const RULE_WIDTH: usize = 40;
+
+/// Renders markdown answers for the terminal. With colors disabled the
+/// text is returned unchanged, which keeps piped output copy-pasteable.
+#[derive(Debug, Clone, Copy)]
+pub struct MarkdownRenderer {
+    color: bool,
+}
+
+impl MarkdownRenderer {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    pub fn render(&self, markdown: &str) -> String {
+        if !self.color {
+            return markdown.to_string();
+        }
+
+        let mut writer = Writer::new();
+        let parser = Parser::new_ext(
+            markdown,
+            Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS,
+        );
+        for event in parser {
+            writer.handle(event);
+        }
+        writer.finish()
+    }
+}
+
+struct Writer {
+    out: String,
+    styles: Vec<ContentStyle>,
+    // One entry per open list, holding the next number for ordered lists
+    lists: Vec<Option<u64>>,
+    quote_depth: usize,
+    code_block: Option<CodeBlock>,
+    link_targets: Vec<String>,
+    at_line_start: bool,
+}
+
+struct CodeBlock {
+    language: String,
+    source: String,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            out: String::new(),
+            styles: Vec::new(),
+            lists: Vec::new(),
+            quote_depth: 0,
+            code_block: None,
+            link_targets: Vec::new(),
+            // So answers that start with a list or a quote get their
+            // marker without a newline in front
+            at_line_start: true,
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        if let Some(ref mut block) = self.code_block {
+            match event {
+                Event::Text(text) => block.source.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    let block = self.code_block.take().unwrap();
+                    self.write_code_block(&block);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                let style = ContentStyle {
+                    foreground_color: Some(Color::Yellow),
+                    ..self.current_style()
+                };
+                self.styled(&code, style);
+            }
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.newline(),
+            Event::Rule => {
+                self.block_separator();
+                self.styled(&"─".repeat(RULE_WIDTH), dim());
+                self.newline();
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::Html(html) | Event::InlineHtml(html) => self.text(&html),
+            Event::InlineMath(math) | Event::DisplayMath(math) => self.text(&math),
+            Event::FootnoteReference(name) => self.text(&format!("[^{}]", name)),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.block_separator(),
+            Tag::Heading { level, .. } => {
+                self.block_separator();
+                let color = match level {
+                    HeadingLevel::H1 => Color::Magenta,
+                    HeadingLevel::H2 => Color::Cyan,
+                    _ => Color::Blue,
+                };
+                let mut style = ContentStyle::new();
+                style.foreground_color = Some(color);
+                style.attributes.set(Attribute::Bold);
+                if level == HeadingLevel::H1 {
+                    style.attributes.set(Attribute::Underlined);
+                }
+                self.styles.push(style);
+            }
+            Tag::BlockQuote(_) => {
+                self.block_separator();
+                self.quote_depth += 1;
+            }
+            Tag::CodeBlock(kind) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_lowercase()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code_block = Some(CodeBlock {
+                    language,
+                    source: String::new(),
+                });
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.block_separator();
+                } else if !self.at_line_start {
+                    self.newline();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                if !self.at_line_start {
+                    self.newline();
+                }
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.text(&indent);
+                self.styled(&marker, fg(Color::Cyan));
+            }
+            Tag::Emphasis => self.push_attribute(Attribute::Italic),
+            Tag::Strong => self.push_attribute(Attribute::Bold),
+            Tag::Strikethrough => self.push_attribute(Attribute::CrossedOut),
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.link_targets.push(dest_url.to_string());
+                let mut style = self.current_style();
+                style.foreground_color = Some(Color::Blue);
+                style.attributes.set(Attribute::Underlined);
+                self.styles.push(style);
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => self.newline(),
+            TagEnd::Heading(_) => {
+                self.styles.pop();
+                self.newline();
+            }
+            TagEnd::BlockQuote(_) => self.quote_depth = self.quote_depth.saturating_sub(1),
+            TagEnd::List(_) => {
+                self.lists.pop();
+                if !self.at_line_start {
+                    self.newline();
+                }
+            }
+            TagEnd::Item if !self.at_line_start => self.newline(),
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
+                self.styles.pop();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                self.styles.pop();
+                if let Some(url) = self.link_targets.pop() {
+                    self.styled(&format!(" ({})", url), dim());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn write_code_block(&mut self, block: &CodeBlock) {
+        self.block_separator();
+        if !block.language.is_empty() {
+            self.styled(&format!("  {}", block.language), dim());
+            self.newline();
+        }
+        for line in block.source.trim_end_matches('\n').lines() {
+            self.text("  ");
+            for (token, style) in highlight_line(&block.language, line) {
+                self.styled(token, style);
+            }
+            self.newline();
+        }
+    }
+
+    fn push_attribute(&mut self, attribute: Attribute) {
+        let mut style = self.current_style();
+        style.attributes.set(attribute);
+        self.styles.push(style);
+    }
+
+    fn current_style(&self) -> ContentStyle {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn text(&mut self, text: &str) {
+        self.styled(text, self.current_style());
+    }
+
+    fn styled(&mut self, text: &str, style: ContentStyle) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            self.line_prefix();
+            self.out
+                .push_str(&StyledContent::new(style, line).to_string());
+        }
+    }
+
+    fn line_prefix(&mut self) {
+        if self.at_line_start {
+            self.at_line_start = false;
+            for _ in 0..self.quote_depth {
+                self.out
+                    .push_str(&StyledContent::new(dim(), "│ ").to_string());
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    /// Separates blocks by a blank line, unless nested inside a list item.
+    fn block_separator(&mut self) {
+        if self.out.is_empty() || !self.lists.is_empty() {
+            return;
+        }
+        if !self.at_line_start {
+            self.newline();
+        }
+        if !self.out.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+
+    fn finish(self) -> String {
+        self.out.trim_end().to_string()
+    }
+}
+
+fn fg(color: Color) -> ContentStyle {
+    ContentStyle {
+        foreground_color: Some(color),
+        ..ContentStyle::new()
+    }
+}
+
+fn dim() -> ContentStyle {
+    let mut style = ContentStyle::new();
+    style.attributes.set(Attribute::Dim);
+    style
+}
+
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comment: &'static str,
+    /// Whether `'` can start a lifetime, as in `&'a str`, rather than a
+    /// quoted literal
+    lifetimes: bool,
+}
+
+fn syntax_for(language: &str) -> Option<Syntax> {
+    let syntax = match language {
+        "rust" | "rs" => Syntax {
+            keywords: &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+                "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+                "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+            line_comment: "//",
+            lifetimes: true,
+        },
+        "python" | "py" => Syntax {
+            keywords: &[
+                "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+                "del", "elif", "else", "except", "False", "finally", "for", "from", "if", "import",
+                "in", "is", "lambda", "None", "not", "or", "pass", "raise", "return", "True",
+                "try", "while", "with", "yield",
+            ],
+            line_comment: "#",
+            lifetimes: false,
+        },
+        "javascript" | "js" | "typescript" | "ts" | "jsx" | "tsx" => Syntax {
+            keywords: &[
+                "async",
+                "await",
+                "break",
+                "case",
+                "catch",
+                "class",
+                "const",
+                "continue",
+                "default",
+                "else",
+                "export",
+                "extends",
+                "false",
+                "finally",
+                "for",
+                "function",
+                "if",
+                "import",
+                "in",
+                "instanceof",
+                "interface",
+                "let",
+                "new",
+                "null",
+                "of",
+                "return",
+                "switch",
+                "this",
+                "throw",
+                "true",
+                "try",
+                "type",
+                "typeof",
+                "undefined",
+                "var",
+                "while",
+                "yield",
+            ],
+            line_comment: "//",
+            lifetimes: false,
+        },
+        "go" => Syntax {
+            keywords: &[
+                "break",
+                "case",
+                "chan",
+                "const",
+                "continue",
+                "default",
+                "defer",
+                "else",
+                "false",
+                "for",
+                "func",
+                "go",
+                "if",
+                "import",
+                "interface",
+                "map",
+                "nil",
+                "package",
+                "range",
+                "return",
+                "select",
+                "struct",
+                "switch",
+                "true",
+                "type",
+                "var",
+            ],
+            line_comment: "//",
+            lifetimes: false,
+        },
+        "c" | "cpp" | "c++" | "h" | "java" | "kotlin" | "swift" | "csharp" | "cs" => Syntax {
+            keywords: &[
+                "break",
+                "case",
+                "class",
+                "const",
+                "continue",
+                "default",
+                "do",
+                "else",
+                "enum",
+                "false",
+                "for",
+                "if",
+                "import",
+                "namespace",
+                "new",
+                "null",
+                "nullptr",
+                "private",
+                "protected",
+                "public",
+                "return",
+                "static",
+                "struct",
+                "switch",
+                "this",
+                "true",
+                "void",
+                "while",
+            ],
+            line_comment: "//",
+            lifetimes: false,
+        },
+        "sh" | "bash" | "shell" | "zsh" | "console" => Syntax {
+            keywords: &[
+                "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function",
+                "if", "in", "local", "then", "while",
+            ],
+            line_comment: "#",
+            lifetimes: false,
+        },
+        "toml" | "yaml" | "yml" => Syntax {
+            keywords: &["true", "false"],
+            line_comment: "#",
+            lifetimes: false,
+        },
+        "json" => Syntax {
+            keywords: &["true", "false", "null"],
+            line_comment: "",
+            lifetimes: false,
+        },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// A small, line-based highlighter: keywords, strings, numbers and line
+/// comments. Good enough to make code stand out without a grammar engine.
+fn highlight_line<'a>(language: &str, line: &'a str) -> Vec<(&'a str, ContentStyle)> {
+    let Some(syntax) = syntax_for(language) else {
+        return vec![(line, ContentStyle::new())];
+    };
+
+    let mut tokens = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        if !syntax.line_comment.is_empty() && rest.starts_with(syntax.line_comment) {
+            tokens.push((rest, dim()));
+            break;
+        }
+
+        let first = rest.chars().next().unwrap();
+        let len = if first == '\'' && syntax.lifetimes && !is_char_literal(rest) {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map(|i| i + 1)
+                .unwrap_or(rest.len());
+            tokens.push((&rest[..end], fg(Color::Cyan)));
+            end
+        } else if first == '"' || first == '\'' || first == '`' {
+            let end = rest[1..]
+                .char_indices()
+                .scan(false, |escaped, (i, c)| {
+                    let closes = !*escaped && c == first;
+                    *escaped = !*escaped && c == '\\';
+                    Some((i, closes))
+                })
+                .find(|(_, closes)| *closes)
+                .map(|(i, _)| i + 2)
+                .unwrap_or(rest.len());
+            tokens.push((&rest[..end], fg(Color::Green)));
+            end
+        } else if first.is_alphanumeric() || first == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let style = if syntax.keywords.contains(&word) {
+                fg(Color::Magenta)
+            } else if first.is_ascii_digit() {
+                fg(Color::Yellow)
+            } else {
+                ContentStyle::new()
+            };
+            tokens.push((word, style));
+            end
+        } else {
+            let len = first.len_utf8();
+            tokens.push((&rest[..len], ContentStyle::new()));
+            len
+        };
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+/// Whether the `'` at the start of the text opens a char literal like `'x'`
+/// or `'\n'`, rather than a lifetime
+fn is_char_literal(text: &str) -> bool {
+    let mut chars = text[1..].chars();
+    match chars.next() {
+        Some('\\') => true,
+        Some(_) => chars.next() == Some('\''),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(markdown: &str) -> String {
+        MarkdownRenderer::new(true).render(markdown)
+    }
+
+    fn styled(text: &str, style: ContentStyle) -> String {
+        StyledContent::new(style, text).to_string()
+    }
+
+    /// The rendered text without the escape sequences for the styles
+    fn plain(rendered: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = rendered.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn leaves_text_alone_without_color() {
+        let markdown = "# Title\n\n- **item**";
+
+        assert_eq!(MarkdownRenderer::new(false).render(markdown), markdown);
+    }
+
+    #[test]
+    fn renders_headings() {
+        let rendered = render("# Title\n\nSome text\n\n## Section");
+
+        assert_eq!(plain(&rendered), "Title\n\nSome text\n\nSection");
+        let mut h1 = fg(Color::Magenta);
+        h1.attributes.set(Attribute::Bold);
+        h1.attributes.set(Attribute::Underlined);
+        assert!(rendered.contains(&styled("Title", h1)), "{:?}", rendered);
+        let mut h2 = fg(Color::Cyan);
+        h2.attributes.set(Attribute::Bold);
+        assert!(rendered.contains(&styled("Section", h2)), "{:?}", rendered);
+    }
+
+    #[test]
+    fn renders_lists() {
+        let rendered = render("- one\n- two\n  1. first\n  2. second\n\nAfter");
+
+        assert_eq!(
+            plain(&rendered),
+            "• one\n• two\n  1. first\n  2. second\n\nAfter"
+        );
+        assert!(rendered.contains(&styled("• ", fg(Color::Cyan))));
+    }
+
+    #[test]
+    fn renders_quotes() {
+        let rendered = render("> quoted\n\nAfter");
+
+        assert_eq!(plain(&rendered), "│ quoted\n\nAfter");
+    }
+
+    #[test]
+    fn renders_code_blocks() {
+        let rendered = render("Run this:\n\n```rust\nlet x = \"a\"; // why\n```");
+
+        assert_eq!(
+            plain(&rendered),
+            "Run this:\n\n  rust\n  let x = \"a\"; // why"
+        );
+        assert!(rendered.contains(&styled("let", fg(Color::Magenta))));
+        assert!(rendered.contains(&styled("\"a\"", fg(Color::Green))));
+        assert!(rendered.contains(&styled("// why", dim())));
+    }
+
+    #[test]
+    fn tells_lifetimes_from_char_literals() {
+        let tokens = highlight_line("rust", "fn f<'a>(s: &'a str) -> char { '\\'' }");
+
+        let lifetimes: Vec<&str> = tokens
+            .iter()
+            .filter(|(_, style)| *style == fg(Color::Cyan))
+            .map(|(token, _)| *token)
+            .collect();
+        assert_eq!(lifetimes, ["'a", "'a"]);
+        let literals: Vec<&str> = tokens
+            .iter()
+            .filter(|(_, style)| *style == fg(Color::Green))
+            .map(|(token, _)| *token)
+            .collect();
+        assert_eq!(literals, ["'\\''"]);
+        // The rest of the line is still highlighted
+        assert!(tokens.contains(&("char", ContentStyle::new())));
+        assert!(tokens.contains(&("str", ContentStyle::new())));
+    }
+}
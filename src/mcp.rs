@@ -0,0 +1,769 @@
+//! Tools from Model Context Protocol servers. A server is a program started
+//! as a subprocess, speaking JSON-RPC over its stdin and stdout. Its tools
+//! are offered to the model next to the built-in ones.
+//!
+//! `serve` is the other side: it offers a `ToolSet` to MCP clients, such as
+//! other agents or editors.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    process::Stdio,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::{FutureExt, future::BoxFuture, future::join_all};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::oneshot,
+};
+
+use crate::{
+    ollama::{FunctionCall, ToolCall, ToolDefinition},
+    tools::{Tool, ToolError, ToolSet},
+};
+
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Protocol versions `serve` accepts. The tools part is the same in all of
+/// them.
+const SUPPORTED_VERSIONS: [&str; 3] = [PROTOCOL_VERSION, "2025-03-26", "2024-11-05"];
+
+/// How long a server may take to start and list its tools
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a tool call may take, unless the server's config says otherwise
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whether the model may call a tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Calls run without asking
+    Allow,
+    /// Every call has to be approved by the user
+    #[default]
+    Ask,
+    /// The tool isn't offered to the model at all
+    Deny,
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::Allow => write!(f, "allow"),
+            Permission::Ask => write!(f, "ask"),
+            Permission::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// How to start a server, from the `[mcp_servers.<name>]` sections of the
+/// config
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Set for the server process, next to the inherited environment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// Applies to all tools of the server, unless overridden in `tools`
+    #[serde(default)]
+    pub permission: Permission,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tools: BTreeMap<String, Permission>,
+    /// Seconds a tool call may take before it is given up on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_timeout: Option<u64>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl McpServerConfig {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            enabled: true,
+            permission: Permission::default(),
+            tools: BTreeMap::new(),
+            call_timeout: None,
+        }
+    }
+
+    pub fn permission_for(&self, tool: &str) -> Permission {
+        self.tools.get(tool).copied().unwrap_or(self.permission)
+    }
+
+    pub fn call_timeout(&self) -> Duration {
+        self.call_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CALL_TIMEOUT)
+    }
+
+    /// The command line, for messages
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.command)
+            .chain(&self.args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug)]
+pub enum McpError {
+    Io(std::io::Error),
+    /// The server sent something that doesn't follow the protocol
+    Protocol(String),
+    /// The server answered a request with an error
+    Server {
+        code: i64,
+        message: String,
+    },
+    /// The server exited or closed its output
+    Closed,
+    Timeout(&'static str),
+}
+
+impl Display for McpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            McpError::Io(error) => write!(f, "{}", error),
+            McpError::Protocol(message) => write!(f, "Protocol error: {}", message),
+            McpError::Server { code, message } => write!(f, "{} (code {})", message, code),
+            McpError::Closed => write!(f, "The server closed the connection"),
+            McpError::Timeout(waiting_for) => write!(f, "No answer to {} in time", waiting_for),
+        }
+    }
+}
+
+impl std::error::Error for McpError {}
+
+impl From<std::io::Error> for McpError {
+    fn from(err: std::io::Error) -> Self {
+        McpError::Io(err)
+    }
+}
+
+type Writer = Arc<tokio::sync::Mutex<Box<dyn AsyncWrite + Send + Unpin>>>;
+/// Requests waiting for their response, by id. `None` once the server is
+/// gone.
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value, McpError>>>>>>;
+
+/// A connection to a server. Requests can be made concurrently, responses
+/// are matched to them by id.
+pub struct McpClient {
+    writer: Writer,
+    pending: Pending,
+    next_id: AtomicU64,
+    server_name: String,
+    /// Killed when the client is dropped
+    _child: Option<Child>,
+}
+
+impl std::fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpClient")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+impl McpClient {
+    /// Starts the server and completes the handshake
+    pub async fn start(config: &McpServerConfig) -> Result<Self, McpError> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(McpError::Protocol("could not attach to the server".into()));
+        };
+
+        // Servers log to stderr, which would end up in the middle of the REPL
+        let command = config.command.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!(server = command, "{}", line);
+            }
+        });
+
+        let mut client = tokio::time::timeout(STARTUP_TIMEOUT, Self::connect(stdout, stdin))
+            .await
+            .map_err(|_| McpError::Timeout("initialize"))??;
+        client._child = Some(child);
+        Ok(client)
+    }
+
+    /// Talks to a server over the given streams, e.g. a subprocess or a
+    /// socket, and completes the handshake
+    pub async fn connect(
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Result<Self, McpError> {
+        let writer: Writer = Arc::new(tokio::sync::Mutex::new(Box::new(writer)));
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        tokio::spawn(read_messages(reader, writer.clone(), pending.clone()));
+
+        let mut client = Self {
+            writer,
+            pending,
+            next_id: AtomicU64::new(1),
+            server_name: String::new(),
+            _child: None,
+        };
+        let result = client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": "ollama_code",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        if result["protocolVersion"].as_str().is_none() {
+            return Err(McpError::Protocol(
+                "initialize response without protocolVersion".into(),
+            ));
+        }
+        client.server_name = result["serverInfo"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        client
+            .notify("notifications/initialized", json!({}))
+            .await?;
+        Ok(client)
+    }
+
+    /// The name the server gave itself during the handshake
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// All tools of the server, following pagination
+    pub async fn list_tools(&self) -> Result<Vec<ToolInfo>, McpError> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+            let page: ToolPage = serde_json::from_value(result)
+                .map_err(|e| McpError::Protocol(format!("invalid tools/list result: {}", e)))?;
+            tools.extend(page.tools);
+            match page.next_cursor {
+                Some(next) if Some(&next) != cursor.as_ref() => cursor = Some(next),
+                _ => return Ok(tools),
+            }
+        }
+    }
+
+    pub async fn call_tool(&self, name: &str, arguments: &Value) -> Result<CallResult, McpError> {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+        serde_json::from_value(result)
+            .map_err(|e| McpError::Protocol(format!("invalid tools/call result: {}", e)))
+    }
+
+    /// Dropping the future gives up on the request: the server is told to
+    /// stop working on it, and a late response is ignored
+    async fn request(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, sender),
+            None => return Err(McpError::Closed),
+        };
+        let mut request = PendingRequest {
+            id,
+            // The handshake itself can't be cancelled
+            cancellable: method != "initialize",
+            writer: self.writer.clone(),
+            pending: self.pending.clone(),
+            done: false,
+        };
+
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(err) = write_message(&self.writer, &message).await {
+            request.cancellable = false;
+            return Err(err);
+        }
+        let result = receiver.await.unwrap_or(Err(McpError::Closed));
+        request.done = true;
+        result
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), McpError> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&self.writer, &message).await
+    }
+}
+
+/// A request waiting for its response. Cleans up when it is given up on.
+struct PendingRequest {
+    id: u64,
+    /// Whether the server has to be told when the request is given up on
+    cancellable: bool,
+    writer: Writer,
+    pending: Pending,
+    done: bool,
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.id);
+        }
+        if !self.cancellable {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let writer = self.writer.clone();
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": self.id, "reason": "The client gave up on the request" },
+        });
+        runtime.spawn(async move {
+            if let Err(err) = write_message(&writer, &message).await {
+                tracing::debug!("Could not cancel MCP request: {}", err);
+            }
+        });
+    }
+}
+
+/// Messages are single lines of JSON
+async fn write_message(writer: &Writer, message: &Value) -> Result<(), McpError> {
+    let mut line = message.to_string();
+    line.push('\n');
+    let mut writer = writer.lock().await;
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Hands responses to the requests waiting for them until the server closes
+/// its output, then fails whatever is still waiting.
+async fn read_messages(reader: impl AsyncRead + Unpin, writer: Writer, pending: Pending) {
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                tracing::warn!("Could not read from MCP server: {}", err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle_message(message, &writer, &pending).await,
+            Err(err) => tracing::warn!("Invalid message from MCP server: {}", err),
+        }
+    }
+    // Dropping the senders wakes the requests up with `Closed`
+    pending.lock().unwrap().take();
+}
+
+async fn handle_message(message: Value, writer: &Writer, pending: &Pending) {
+    if let Some(method) = message["method"].as_str() {
+        // Requests from the server. We don't offer anything but ping.
+        if let Some(id) = message.get("id") {
+            let response = if method == "ping" {
+                json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+            } else {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("Method not found: {}", method) },
+                })
+            };
+            if let Err(err) = write_message(writer, &response).await {
+                tracing::warn!("Could not answer MCP server: {}", err);
+            }
+        } else {
+            tracing::debug!(method, "notification from MCP server");
+        }
+        return;
+    }
+
+    let Some(id) = message["id"].as_u64() else {
+        tracing::warn!("MCP response without id: {}", message);
+        return;
+    };
+    let Some(sender) = pending
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|pending| pending.remove(&id))
+    else {
+        // The request was given up on, e.g. cancelled
+        return;
+    };
+    let result = match message.get("error") {
+        Some(error) => Err(McpError::Server {
+            code: error["code"].as_i64().unwrap_or_default(),
+            message: error["message"].as_str().unwrap_or_default().to_string(),
+        }),
+        None => Ok(message["result"].clone()),
+    };
+    let _ = sender.send(result);
+}
+
+/// A tool as the server describes it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Option<Value>,
+    #[serde(default)]
+    pub annotations: ToolAnnotations,
+}
+
+/// Hints about what a tool does. Servers aren't required to send them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    #[serde(default)]
+    pub read_only_hint: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolPage {
+    tools: Vec<ToolInfo>,
+    #[serde(default)]
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallResult {
+    #[serde(default)]
+    pub content: Vec<Value>,
+    #[serde(default)]
+    pub structured_content: Option<Value>,
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+impl CallResult {
+    /// The text for the model. Content it can't read, such as images, is
+    /// only mentioned.
+    pub fn text(&self) -> String {
+        if self.content.is_empty()
+            && let Some(structured) = &self.structured_content
+        {
+            return structured.to_string();
+        }
+        self.content
+            .iter()
+            .map(|item| match item["type"].as_str() {
+                Some("text") => item["text"].as_str().unwrap_or_default().to_string(),
+                Some(kind) => format!("[{} content]", kind),
+                None => item.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// A tool of an MCP server. Calls go to the server.
+#[derive(Debug, Clone)]
+pub struct McpTool {
+    client: Arc<McpClient>,
+    server: String,
+    definition: ToolDefinition,
+    read_only: bool,
+    permission: Permission,
+    timeout: Duration,
+}
+
+impl McpTool {
+    /// The name of the server in the config
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn permission(&self) -> Permission {
+        self.permission
+    }
+}
+
+impl Tool for McpTool {
+    fn definition(&self) -> ToolDefinition {
+        self.definition.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn needs_approval(&self) -> bool {
+        self.permission == Permission::Ask
+    }
+
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>> {
+        async move {
+            let name = &self.definition.function.name;
+            let result = tokio::time::timeout(self.timeout, self.client.call_tool(name, arguments))
+                .await
+                .unwrap_or(Err(McpError::Timeout("tools/call")));
+            match result {
+                Ok(result) if result.is_error => Err(ToolError::Failed(result.text())),
+                Ok(result) => Ok(result.text()),
+                // E.g. invalid arguments, the model can fix those
+                Err(McpError::Server { message, .. }) => Err(ToolError::Failed(message)),
+                // The server might just be slow with this call
+                Err(McpError::Timeout(_)) => Err(ToolError::Failed(format!(
+                    "MCP server '{}' didn't answer within {}s",
+                    self.server,
+                    self.timeout.as_secs_f64()
+                ))),
+                Err(err) => Err(ToolError::Broken(format!(
+                    "MCP server '{}': {}",
+                    self.server, err
+                ))),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// A connected server and the tools it offers, without the denied ones
+#[derive(Debug)]
+pub struct McpServer {
+    name: String,
+    tools: Vec<McpTool>,
+}
+
+impl McpServer {
+    pub async fn start(name: &str, config: &McpServerConfig) -> Result<Self, McpError> {
+        let client = McpClient::start(config).await?;
+        tokio::time::timeout(STARTUP_TIMEOUT, Self::new(name, client, config))
+            .await
+            .map_err(|_| McpError::Timeout("tools/list"))?
+    }
+
+    /// Lists the tools of a connected server and applies the permissions
+    /// from the config
+    pub async fn new(
+        name: &str,
+        client: McpClient,
+        config: &McpServerConfig,
+    ) -> Result<Self, McpError> {
+        let client = Arc::new(client);
+        let tools = client
+            .list_tools()
+            .await?
+            .into_iter()
+            .filter_map(|info| {
+                let permission = config.permission_for(&info.name);
+                if permission == Permission::Deny {
+                    return None;
+                }
+                let description = info
+                    .description
+                    .unwrap_or_else(|| format!("Provided by the {} MCP server", name));
+                Some(McpTool {
+                    client: client.clone(),
+                    server: name.to_string(),
+                    definition: ToolDefinition::function(
+                        &info.name,
+                        &description,
+                        info.input_schema,
+                    ),
+                    read_only: info.annotations.read_only_hint,
+                    permission,
+                    timeout: config.call_timeout(),
+                })
+            })
+            .collect();
+        Ok(Self {
+            name: name.to_string(),
+            tools,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tools(&self) -> &[McpTool] {
+        &self.tools
+    }
+}
+
+/// Starts all enabled servers at the same time. Servers that fail to start
+/// are returned with their error, so the others can still be used.
+pub async fn start_all(
+    servers: &BTreeMap<String, McpServerConfig>,
+) -> Vec<Result<McpServer, (String, McpError)>> {
+    join_all(
+        servers
+            .iter()
+            .filter(|(_, config)| config.enabled)
+            .map(|(name, config)| async move {
+                McpServer::start(name, config)
+                    .await
+                    .map_err(|err| (name.clone(), err))
+            }),
+    )
+    .await
+}
+
+/// Offers the tools to the client on the other end of the streams until it
+/// closes its side. Calls are validated and run exactly like the
+/// assistant's own calls. Requests are answered one at a time, in order.
+pub async fn serve(
+    tools: &ToolSet,
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+) -> Result<(), McpError> {
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => answer(tools, &message).await,
+            Err(err) => Some(json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": -32700, "message": format!("Parse error: {}", err) },
+            })),
+        };
+        if let Some(response) = response {
+            let mut line = response.to_string();
+            line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+            writer.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// The response to a request, or `None` for notifications
+async fn answer(tools: &ToolSet, message: &Value) -> Option<Value> {
+    let id = message.get("id")?;
+    let params = &message["params"];
+    let result = match message["method"].as_str().unwrap_or_default() {
+        "initialize" => {
+            let requested = params["protocolVersion"].as_str().unwrap_or_default();
+            let version = SUPPORTED_VERSIONS
+                .into_iter()
+                .find(|version| *version == requested)
+                .unwrap_or(PROTOCOL_VERSION);
+            Ok(json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": { "listChanged": false } },
+                "serverInfo": { "name": "ollama_code", "version": env!("CARGO_PKG_VERSION") },
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": list(tools) })),
+        "tools/call" => call(tools, params).await,
+        method => Err((-32601, format!("Method not found: {}", method))),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    })
+}
+
+fn list(tools: &ToolSet) -> Vec<Value> {
+    tools
+        .tools()
+        .iter()
+        .map(|tool| {
+            let definition = tool.definition().function;
+            json!({
+                "name": definition.name,
+                "description": definition.description,
+                "inputSchema": definition
+                    .parameters
+                    .unwrap_or_else(|| json!({ "type": "object" })),
+                "annotations": { "readOnlyHint": tool.is_read_only() },
+            })
+        })
+        .collect()
+}
+
+/// Unknown tools are protocol errors. Everything that goes wrong with a
+/// call to a known tool is a result with `isError` set, for the model to
+/// read.
+async fn call(tools: &ToolSet, params: &Value) -> Result<Value, (i64, String)> {
+    let Some(name) = params["name"].as_str() else {
+        return Err((-32602, "Missing tool name".into()));
+    };
+    if !tools.names().iter().any(|known| known == name) {
+        return Err((-32602, format!("Unknown tool: {}", name)));
+    }
+
+    let call = ToolCall {
+        id: None,
+        function: FunctionCall {
+            name: name.to_string(),
+            arguments: params["arguments"].clone(),
+        },
+    };
+    let result = match tools.prepare(&call) {
+        Ok(prepared) => prepared.run().await,
+        Err(error) => Err(ToolError::Failed(error)),
+    };
+    let (text, is_error) = match result {
+        Ok(output) => (output, false),
+        Err(error) => (error.to_string(), true),
+    };
+    Ok(json!({
+        "content": [{ "type": "text", "text": text }],
+        "isError": is_error,
+    }))
+}
@@ -0,0 +1,256 @@
+use std::fmt::Display;
+
+use futures::future::join_all;
+
+use crate::{
+    ollama::{OllamaClient, OllamaError, ShowResponse},
+    table,
+};
+
+const TOOLS_CAPABILITY: &str = "tools";
+
+/// What we need to know about an installed model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub name: String,
+    pub parameter_size: String,
+    pub quantization: String,
+    pub context_length: Option<u64>,
+    pub capabilities: Vec<String>,
+}
+
+impl ModelInfo {
+    fn from_show(name: &str, show: ShowResponse) -> Self {
+        // The key is prefixed with the architecture, e.g. `llama.context_length`
+        let context_length = show
+            .model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64());
+
+        Self {
+            name: name.to_string(),
+            parameter_size: show.details.parameter_size,
+            quantization: show.details.quantization_level,
+            context_length,
+            capabilities: show.capabilities,
+        }
+    }
+
+    /// Older Ollama versions don't report capabilities at all. In that case
+    /// we can't tell and assume the model supports tools.
+    pub fn supports_tools(&self) -> bool {
+        self.capabilities.is_empty() || self.capabilities.iter().any(|c| c == TOOLS_CAPABILITY)
+    }
+}
+
+impl Display for ModelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut details = Vec::new();
+        if !self.parameter_size.is_empty() {
+            details.push(self.parameter_size.clone());
+        }
+        if !self.quantization.is_empty() {
+            details.push(self.quantization.clone());
+        }
+        if let Some(context_length) = self.context_length {
+            details.push(format!("context {} tokens", context_length));
+        }
+        if !self.capabilities.is_empty() {
+            details.push(self.capabilities.join(", "));
+        }
+        write!(f, "{} ({})", self.name, details.join(", "))
+    }
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    Ollama(OllamaError),
+    NotInstalled {
+        model: String,
+        suggestions: Vec<String>,
+    },
+}
+
+impl From<OllamaError> for ModelError {
+    fn from(err: OllamaError) -> Self {
+        ModelError::Ollama(err)
+    }
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (model, suggestions) = match self {
+            ModelError::Ollama(error) => return write!(f, "ollama unavailable: {}", error),
+            ModelError::NotInstalled { model, suggestions } => (model, suggestions),
+        };
+
+        write!(
+            f,
+            "Model '{}' is not installed. Pull it with `ollama pull {}`",
+            model, model
+        )?;
+        if !suggestions.is_empty() {
+            write!(
+                f,
+                "\nInstalled models with tool support: {}",
+                suggestions.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+/// Lists installed models, with their details from /api/show
+pub async fn list(client: &OllamaClient) -> Result<Vec<ModelInfo>, OllamaError> {
+    let tags = client.tags().await?;
+    let infos = join_all(tags.models.iter().map(|model| async {
+        let show = client.show(&model.name).await?;
+        Ok(ModelInfo::from_show(&model.name, show))
+    }))
+    .await;
+
+    let mut infos = infos.into_iter().collect::<Result<Vec<_>, OllamaError>>()?;
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(infos)
+}
+
+/// Makes sure the model is installed. Suggests installed alternatives that
+/// support tools if it isn't.
+#[tracing::instrument(skip(client))]
+pub async fn check(client: &OllamaClient, model: &str) -> Result<ModelInfo, ModelError> {
+    let tags = client.tags().await?;
+    let Some(installed) = tags.models.iter().find(|m| same_model(&m.name, model)) else {
+        return Err(ModelError::NotInstalled {
+            model: model.to_string(),
+            suggestions: suggestions(client).await,
+        });
+    };
+
+    Ok(ModelInfo::from_show(
+        &installed.name,
+        client.show(&installed.name).await?,
+    ))
+}
+
+/// Installed models that support tools
+pub async fn suggestions(client: &OllamaClient) -> Vec<String> {
+    list(client)
+        .await
+        .map(|models| tool_capable(&models))
+        .unwrap_or_default()
+}
+
+fn tool_capable(models: &[ModelInfo]) -> Vec<String> {
+    models
+        .iter()
+        .filter(|info| info.supports_tools())
+        .map(|info| info.name.clone())
+        .collect()
+}
+
+/// `llama3.1` and `llama3.1:latest` name the same model
+fn same_model(installed: &str, requested: &str) -> bool {
+    let with_tag = |name: &str| {
+        if name.contains(':') {
+            name.to_string()
+        } else {
+            format!("{}:latest", name)
+        }
+    };
+    with_tag(installed) == with_tag(requested)
+}
+
+/// Renders the installed models as a table for the `models` subcommand
+pub fn table(models: &[ModelInfo]) -> String {
+    let rows: Vec<[String; 5]> = models
+        .iter()
+        .map(|info| {
+            [
+                info.name.clone(),
+                info.parameter_size.clone(),
+                info.quantization.clone(),
+                info.context_length
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                if info.supports_tools() { "yes" } else { "no" }.to_string(),
+            ]
+        })
+        .collect();
+
+    table::render(["NAME", "SIZE", "QUANTIZATION", "CONTEXT", "TOOLS"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn info(name: &str, capabilities: &[&str]) -> ModelInfo {
+        ModelInfo {
+            name: name.into(),
+            parameter_size: "8.0B".into(),
+            quantization: "Q4_K_M".into(),
+            context_length: Some(8192),
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn treats_latest_as_the_default_tag() {
+        assert!(same_model("llama3.1:latest", "llama3.1"));
+        assert!(same_model("llama3.1", "llama3.1:latest"));
+        assert!(same_model("llama3.1:8b", "llama3.1:8b"));
+        assert!(!same_model("llama3.1:8b", "llama3.1"));
+        assert!(!same_model("llama3.1:latest", "llama3"));
+    }
+
+    #[test]
+    fn tells_which_models_support_tools() {
+        assert!(info("a", &["completion", "tools"]).supports_tools());
+        assert!(!info("b", &["completion", "vision"]).supports_tools());
+        // Older Ollama versions don't say
+        assert!(info("c", &[]).supports_tools());
+        assert_eq!(
+            tool_capable(&[info("a", &["tools"]), info("b", &["completion"])]),
+            ["a"]
+        );
+    }
+
+    #[test]
+    fn reads_the_context_length_of_any_architecture() {
+        let show: ShowResponse = serde_json::from_value(json!({
+            "details": { "parameter_size": "7.6B", "quantization_level": "Q4_K_M" },
+            "model_info": { "general.architecture": "qwen2", "qwen2.context_length": 32768 },
+            "capabilities": ["completion", "tools"],
+        }))
+        .unwrap();
+
+        let info = ModelInfo::from_show("qwen2.5-coder:7b", show);
+
+        assert_eq!(info.context_length, Some(32768));
+        assert_eq!(info.parameter_size, "7.6B");
+        assert_eq!(
+            info.to_string(),
+            "qwen2.5-coder:7b (7.6B, Q4_K_M, context 32768 tokens, completion, tools)"
+        );
+        let show: ShowResponse = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(ModelInfo::from_show("old", show).context_length, None);
+    }
+
+    #[test]
+    fn renders_a_table() {
+        let mut unknown = info("gemma2:2b", &["completion"]);
+        unknown.context_length = None;
+
+        assert_eq!(
+            table(&[info("llama3.1:8b", &["tools"]), unknown]),
+            "NAME         SIZE  QUANTIZATION  CONTEXT  TOOLS\n\
+             llama3.1:8b  8.0B  Q4_K_M        8192     yes\n\
+             gemma2:2b    8.0B  Q4_K_M        -        no"
+        );
+    }
+}
@@ -1,14 +1,79 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, time::Duration};
 
-use reqwest_streams::error::StreamBodyError;
-use serde_json::json;
+use futures::{Stream, StreamExt};
+use reqwest::StatusCode;
+use reqwest_streams::{
+    JsonStreamResponse,
+    error::{StreamBodyError, StreamBodyKind},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+
+pub const DEFAULT_URL: &str = "http://localhost:11434";
+
+/// Upper bound for a single line in a streamed response
+const MAX_STREAM_LINE: usize = 16 * 1024 * 1024;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum OllamaError {
     ReqwestError(reqwest::Error),
     StreamError(StreamBodyError),
     JsonError(serde_json::Error),
     IoError(std::io::Error),
+    /// The server answered with a non-success status code
+    HttpError {
+        status: StatusCode,
+        message: String,
+    },
+    ModelNotFoundError(String),
+    /// The server reported an error in the middle of a streamed response,
+    /// e.g. the model failed while generating
+    StreamedError(String),
+    /// The response body didn't have the expected shape
+    MalformedResponseError(String),
+    /// Replaying a recorded session failed, or the recorded request failed
+    ReplayError(String),
+    /// Nothing arrived within the configured time
+    TimeoutError {
+        waiting_for: &'static str,
+        after: Duration,
+    },
+}
+
+impl OllamaError {
+    /// Errors that might go away when the request is made again: the server
+    /// couldn't be reached, was overloaded, still loading the model or took
+    /// too long
+    pub fn is_transient(&self) -> bool {
+        match self {
+            OllamaError::HttpError { status, .. } => {
+                (*status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error())
+                    && !self.is_context_overflow()
+            }
+            OllamaError::ReqwestError(error) => !error.is_decode(),
+            OllamaError::StreamError(error) => {
+                matches!(error.kind(), StreamBodyKind::InputOutputError)
+            }
+            OllamaError::IoError(_) | OllamaError::TimeoutError { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Servers word this differently, e.g. "maximum context length is 4096
+    /// tokens" or "the request exceeds the available context size"
+    pub fn is_context_overflow(&self) -> bool {
+        let (OllamaError::HttpError { message, .. } | OllamaError::StreamedError(message)) = self
+        else {
+            return false;
+        };
+        let message = message.to_lowercase();
+        message.contains("context_length_exceeded")
+            || (message.contains("context")
+                && ["exceed", "maximum", "too long"]
+                    .iter()
+                    .any(|hint| message.contains(hint)))
+    }
 }
 
 impl From<StreamBodyError> for OllamaError {
@@ -40,25 +105,415 @@ impl Display for OllamaError {
         match self {
             OllamaError::ReqwestError(error) => write!(f, "{}", error),
             OllamaError::StreamError(stream_body_error) => write!(f, "{}", stream_body_error),
-            // OllamaError::SendError(send_error) => write!(f, "{}", send_error),
             OllamaError::JsonError(json_error) => write!(f, "{}", json_error),
             OllamaError::IoError(io_error) => write!(f, "{}", io_error),
+            OllamaError::HttpError { status, message } => write!(f, "{}: {}", status, message),
+            OllamaError::ModelNotFoundError(model) => {
+                write!(
+                    f,
+                    "Model '{}' not found. Pull it with `ollama pull {}`",
+                    model, model
+                )
+            }
+            OllamaError::StreamedError(message) => write!(f, "{}", message),
+            OllamaError::MalformedResponseError(message) => {
+                write!(f, "Malformed response: {}", message)
+            }
+            OllamaError::ReplayError(message) => write!(f, "Replay: {}", message),
+            OllamaError::TimeoutError { waiting_for, after } => {
+                write!(f, "No {} within {}s", waiting_for, after.as_secs_f64())
+            }
+        }
+    }
+}
+
+impl std::error::Error for OllamaError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// For tool results, the name of the tool that produced them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+    /// For tool results, the id of the call they answer, if the model gave one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            thinking: None,
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_name: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    pub fn tool_calls(tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::new(Role::Assistant, "")
+        }
+    }
+
+    pub fn tool_result(call: &ToolCall, content: impl Into<String>) -> Self {
+        Self {
+            tool_name: Some(call.function.name.clone()),
+            tool_call_id: call.id.clone(),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+}
+
+impl ToolDefinition {
+    pub fn function(name: &str, description: &str, parameters: Option<Value>) -> Self {
+        Self {
+            kind: "function".into(),
+            function: FunctionDefinition {
+                name: name.into(),
+                description: description.into(),
+                parameters,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+    #[serde(default)]
+    pub stream: bool,
+    /// Either "json" or a JSON schema the response has to follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<Value>,
+}
+
+impl ChatRequest {
+    pub fn new(model: &str, messages: Vec<ChatMessage>) -> Self {
+        Self {
+            model: model.to_string(),
+            messages,
+            tools: Vec::new(),
+            stream: false,
+            format: None,
+            options: None,
+            keep_alive: None,
         }
     }
 }
 
-pub async fn check_available(model: &str) -> Result<(), OllamaError> {
-    let client = reqwest::Client::new();
-    let messages: Vec<serde_json::Value> = vec![];
-    client
-        .post("http://localhost:11434/api/chat")
-        .json(&json!({
-            "model": model,
-            "messages": messages
-        }))
-        .send()
-        .await?
-        .error_for_status()?;
+/// A chat response, or one chunk of a streamed response. Timing and token
+/// counts are only set on the final chunk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatResponse {
+    pub model: String,
+    #[serde(default)]
+    pub created_at: String,
+    pub message: ChatMessage,
+    pub done: bool,
+    #[serde(default)]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub total_duration: Option<u64>,
+    #[serde(default)]
+    pub load_duration: Option<u64>,
+    #[serde(default)]
+    pub prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    pub prompt_eval_duration: Option<u64>,
+    #[serde(default)]
+    pub eval_count: Option<u64>,
+    #[serde(default)]
+    pub eval_duration: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ModelDetails {
+    #[serde(default)]
+    pub parent_model: String,
+    #[serde(default)]
+    pub format: String,
+    #[serde(default)]
+    pub family: String,
+    #[serde(default)]
+    pub families: Option<Vec<String>>,
+    #[serde(default)]
+    pub parameter_size: String,
+    #[serde(default)]
+    pub quantization_level: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSummary {
+    pub name: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub modified_at: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListModelsResponse {
+    pub models: Vec<ModelSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShowResponse {
+    #[serde(default)]
+    pub modelfile: String,
+    #[serde(default)]
+    pub parameters: String,
+    #[serde(default)]
+    pub template: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+    /// Architecture specific metadata, keyed like `llama.context_length`
+    #[serde(default)]
+    pub model_info: HashMap<String, Value>,
+    /// What the model supports, e.g. "completion", "tools", "vision"
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// One status update while pulling a model. `total` and `completed` are
+/// set while a layer is downloading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PullProgress {
+    pub status: String,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+}
+
+/// Client for the Ollama REST API
+#[derive(Debug, Clone)]
+pub struct OllamaClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl Default for OllamaClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_URL)
+    }
+}
+
+impl OllamaClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    #[tracing::instrument(skip(self, request), fields(model = %request.model))]
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse, OllamaError> {
+        let request = ChatRequest {
+            stream: false,
+            ..request.clone()
+        };
+        let response = self
+            .http
+            .post(self.url("/api/chat"))
+            .json(&request)
+            .send()
+            .await?;
+        parse_json(check_status(response, &request.model).await?).await
+    }
+
+    /// Streams the response chunk by chunk. The last chunk has `done` set.
+    #[tracing::instrument(skip(self, request), fields(model = %request.model))]
+    pub async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<impl Stream<Item = Result<ChatResponse, OllamaError>> + use<>, OllamaError> {
+        let request = ChatRequest {
+            stream: true,
+            ..request.clone()
+        };
+        let response = self
+            .http
+            .post(self.url("/api/chat"))
+            .json(&request)
+            .send()
+            .await?;
+        Ok(parse_stream(check_status(response, &request.model).await?))
+    }
+
+    /// Lists the models installed locally
+    #[tracing::instrument(skip(self))]
+    pub async fn tags(&self) -> Result<ListModelsResponse, OllamaError> {
+        let response = self.http.get(self.url("/api/tags")).send().await?;
+        parse_json(check_status(response, "").await?).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn show(&self, model: &str) -> Result<ShowResponse, OllamaError> {
+        let response = self
+            .http
+            .post(self.url("/api/show"))
+            .json(&json!({ "model": model }))
+            .send()
+            .await?;
+        parse_json(check_status(response, model).await?).await
+    }
+
+    /// Downloads a model, reporting progress as it goes
+    #[tracing::instrument(skip(self))]
+    pub async fn pull(
+        &self,
+        model: &str,
+    ) -> Result<impl Stream<Item = Result<PullProgress, OllamaError>> + use<>, OllamaError> {
+        let response = self
+            .http
+            .post(self.url("/api/pull"))
+            .json(&json!({ "model": model, "stream": true }))
+            .send()
+            .await?;
+        Ok(parse_stream(check_status(response, model).await?))
+    }
+}
+
+/// Turns error statuses into errors, using the message Ollama puts into
+/// the body. A 404 for a model means it isn't installed.
+pub async fn check_status(
+    response: reqwest::Response,
+    model: &str,
+) -> Result<reqwest::Response, OllamaError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    // Ollama sends `{"error": "..."}`, OpenAI compatible servers
+    // `{"error": {"message": "..."}}`
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|v| {
+            v["error"]
+                .as_str()
+                .or_else(|| v["error"]["message"].as_str())
+                .map(String::from)
+        })
+        .unwrap_or(body);
+
+    if status == StatusCode::NOT_FOUND && !model.is_empty() {
+        Err(OllamaError::ModelNotFoundError(model.to_string()))
+    } else {
+        Err(OllamaError::HttpError { status, message })
+    }
+}
+
+pub async fn parse_json<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T, OllamaError> {
+    let body = response.text().await?;
+    serde_json::from_str(&body)
+        .map_err(|err| OllamaError::MalformedResponseError(format!("{} - {}", err, body)))
+}
 
-    Ok(())
+/// Ollama streams newline delimited JSON. Errors that happen after the
+/// response started arrive as `{"error": "..."}` lines.
+fn parse_stream<T: DeserializeOwned + Send + 'static>(
+    response: reqwest::Response,
+) -> impl Stream<Item = Result<T, OllamaError>> {
+    response
+        .json_nl_stream::<Value>(MAX_STREAM_LINE)
+        .map(|chunk| {
+            let chunk = chunk?;
+            if let Some(error) = chunk.get("error") {
+                return Err(OllamaError::StreamedError(
+                    error.as_str().unwrap_or_default().to_string(),
+                ));
+            }
+            serde_json::from_value(chunk.clone())
+                .map_err(|err| OllamaError::MalformedResponseError(format!("{} - {}", err, chunk)))
+        })
 }
@@ -0,0 +1,409 @@
+use std::collections::BTreeMap;
+
+use futures::{Stream, StreamExt, TryStreamExt, future::ready, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::io::AsyncBufReadExt;
+use tokio_stream::wrappers::LinesStream;
+use tokio_util::io::StreamReader;
+
+use crate::ollama::{
+    ChatMessage, ChatRequest, ChatResponse, FunctionCall, OllamaError, Role, ToolCall,
+    ToolDefinition, check_status, parse_json,
+};
+
+pub const DEFAULT_URL: &str = "http://localhost:8080/v1";
+
+/// Client for servers that implement the OpenAI chat completions API, such
+/// as llama.cpp's `llama-server`, vLLM and LM Studio. Requests and responses
+/// are translated from and to the Ollama types, so the rest of the code only
+/// deals with one format.
+#[derive(Debug, Clone)]
+pub struct OpenAiClient {
+    base_url: String,
+    api_key: Option<String>,
+    http: reqwest::Client,
+}
+
+impl OpenAiClient {
+    pub fn new(base_url: &str, api_key: Option<String>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.http.post(format!("{}{}", self.base_url, path)))
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    /// Streams the response chunk by chunk. Content arrives as deltas, tool
+    /// calls are put together and returned with the last chunk, which has
+    /// `done` set and comes at the end of the stream. Servers send the token
+    /// usage after the finish reason, and some don't send a finish reason
+    /// at all.
+    #[tracing::instrument(skip(self, request), fields(model = %request.model))]
+    pub async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<impl Stream<Item = Result<ChatResponse, OllamaError>> + use<>, OllamaError> {
+        let response = self
+            .post("/chat/completions")
+            .json(&CompletionRequest::from_chat(request, true))
+            .send()
+            .await?;
+        let response = check_status(response, "").await?;
+
+        let bytes = response.bytes_stream().map_err(std::io::Error::other);
+        let lines = LinesStream::new(StreamReader::new(bytes).lines());
+        let events = lines
+            .map_err(OllamaError::from)
+            .try_filter_map(|line| ready(Ok(server_sent_data(&line))))
+            .boxed();
+
+        Ok(stream::unfold(
+            Some((events, StreamState::default())),
+            |state| async move {
+                let (mut events, mut state) = state?;
+                match events.next().await {
+                    Some(Ok(data)) if data == "[DONE]" => Some((Ok(state.finish()), None)),
+                    Some(Ok(data)) => match state.chunk(&data) {
+                        Ok(chunk) => Some((Ok(chunk), Some((events, state)))),
+                        Err(err) => Some((Err(err), None)),
+                    },
+                    Some(Err(err)) => Some((Err(err), None)),
+                    None => Some((Ok(state.finish()), None)),
+                }
+            },
+        ))
+    }
+
+    /// Lists the ids of the models the server offers
+    #[tracing::instrument(skip(self))]
+    pub async fn models(&self) -> Result<Vec<String>, OllamaError> {
+        let response = self
+            .authorize(self.http.get(format!("{}/models", self.base_url)))
+            .send()
+            .await?;
+        let models: ModelList = parse_json(check_status(response, "").await?).await?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+/// The payload of a `data:` line of a server-sent event stream
+fn server_sent_data(line: &str) -> Option<String> {
+    line.strip_prefix("data:")
+        .map(|data| data.trim().to_string())
+}
+
+#[derive(Debug, Serialize)]
+struct CompletionRequest {
+    model: String,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(flatten)]
+    sampling: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+impl CompletionRequest {
+    fn from_chat(request: &ChatRequest, stream: bool) -> Self {
+        // `format` is left out on purpose. Servers disagree on which kinds of
+        // `response_format` they accept, and the templates that want JSON
+        // answers spell out the format in the prompt.
+        Self {
+            model: request.model.clone(),
+            messages: request.messages.iter().map(Message::from_chat).collect(),
+            // Tool definitions already have the OpenAI shape
+            tools: request.tools.clone(),
+            stream,
+            // Without this, streamed responses don't report token usage
+            stream_options: stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+            sampling: sampling_options(request.options.as_ref()),
+        }
+    }
+}
+
+/// Maps Ollama options to their OpenAI names. `top_k` and `repeat_penalty`
+/// aren't part of the OpenAI API, but the local servers understand them.
+/// `num_ctx` is fixed when the server loads the model and is dropped.
+fn sampling_options(options: Option<&Value>) -> Map<String, Value> {
+    let mut sampling = Map::new();
+    let Some(options) = options.and_then(Value::as_object) else {
+        return sampling;
+    };
+
+    for (key, value) in options {
+        let key = match key.as_str() {
+            "temperature" | "top_p" | "top_k" | "seed" | "repeat_penalty" => key.as_str(),
+            "num_predict" => "max_tokens",
+            "stop" if value.as_array().is_some_and(|stop| !stop.is_empty()) => "stop",
+            _ => continue,
+        };
+        sampling.insert(key.to_string(), value.clone());
+    }
+    sampling
+}
+
+#[derive(Debug, Serialize)]
+struct Message {
+    role: Role,
+    content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<WireToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl Message {
+    fn from_chat(message: &ChatMessage) -> Self {
+        Self {
+            role: message.role,
+            content: message.content.clone(),
+            tool_calls: message
+                .tool_calls
+                .iter()
+                .map(WireToolCall::from_chat)
+                .collect(),
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+}
+
+/// A tool call as OpenAI sends it: the arguments are a JSON encoded string
+#[derive(Debug, Serialize)]
+struct WireToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: WireFunctionCall,
+}
+
+#[derive(Debug, Serialize)]
+struct WireFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+fn function_kind() -> String {
+    "function".into()
+}
+
+impl WireToolCall {
+    fn from_chat(call: &ToolCall) -> Self {
+        Self {
+            id: call.id.clone().unwrap_or_default(),
+            kind: function_kind(),
+            function: WireFunctionCall {
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.to_string(),
+            },
+        }
+    }
+
+    fn into_chat(self) -> ToolCall {
+        ToolCall {
+            id: (!self.id.is_empty()).then_some(self.id),
+            function: FunctionCall {
+                name: self.function.name,
+                arguments: parse_arguments(&self.function.arguments),
+            },
+        }
+    }
+}
+
+/// Models sometimes produce arguments that aren't valid JSON. They are kept
+/// as a string, so validation can tell the model what's wrong.
+fn parse_arguments(arguments: &str) -> Value {
+    if arguments.trim().is_empty() {
+        return Value::Object(Map::new());
+    }
+    serde_json::from_str(arguments).unwrap_or_else(|_| Value::String(arguments.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionChunk {
+    #[serde(default)]
+    model: String,
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: Delta,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+/// A piece of a streamed tool call. The first piece for an index carries
+/// the id and name, the arguments are spread over all of them.
+#[derive(Debug, Deserialize)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+/// What the last chunk reports: tool calls put together from the deltas
+/// seen so far, by index, and the details that arrive along the way
+#[derive(Debug, Default)]
+struct StreamState {
+    tool_calls: BTreeMap<usize, WireToolCall>,
+    model: String,
+    finish_reason: Option<String>,
+    usage: Option<Usage>,
+}
+
+impl StreamState {
+    fn chunk(&mut self, data: &str) -> Result<ChatResponse, OllamaError> {
+        let malformed = |err: serde_json::Error| {
+            OllamaError::MalformedResponseError(format!("{} - {}", err, data))
+        };
+        let value: Value = serde_json::from_str(data).map_err(malformed)?;
+        // Errors after the response started, e.g. when the context runs
+        // out, arrive as an event of their own
+        if let Some(error) = value.get("error") {
+            let message = error
+                .as_str()
+                .or_else(|| error["message"].as_str())
+                .map(String::from)
+                .unwrap_or_else(|| error.to_string());
+            return Err(OllamaError::StreamedError(message));
+        }
+        let chunk: CompletionChunk = serde_json::from_value(value).map_err(malformed)?;
+        if !chunk.model.is_empty() {
+            self.model = chunk.model;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        let choice = chunk.choices.into_iter().next();
+        if let Some(finish_reason) = choice.as_ref().and_then(|c| c.finish_reason.clone()) {
+            self.finish_reason = Some(finish_reason);
+        }
+        let delta = choice.map(|c| c.delta).unwrap_or_default();
+
+        for part in delta.tool_calls {
+            let call = self
+                .tool_calls
+                .entry(part.index)
+                .or_insert_with(|| WireToolCall {
+                    id: String::new(),
+                    kind: function_kind(),
+                    function: WireFunctionCall {
+                        name: String::new(),
+                        arguments: String::new(),
+                    },
+                });
+            if let Some(id) = part.id {
+                call.id = id;
+            }
+            if let Some(function) = part.function {
+                call.function
+                    .name
+                    .push_str(&function.name.unwrap_or_default());
+                call.function
+                    .arguments
+                    .push_str(&function.arguments.unwrap_or_default());
+            }
+        }
+
+        Ok(self.response(
+            ChatMessage::assistant(delta.content.unwrap_or_default()),
+            false,
+        ))
+    }
+
+    /// The last chunk, once the stream has ended
+    fn finish(mut self) -> ChatResponse {
+        let mut message = ChatMessage::assistant(String::new());
+        message.tool_calls = std::mem::take(&mut self.tool_calls)
+            .into_values()
+            .map(WireToolCall::into_chat)
+            .collect();
+        self.response(message, true)
+    }
+
+    fn response(&self, message: ChatMessage, done: bool) -> ChatResponse {
+        let usage = self.usage.filter(|_| done);
+        ChatResponse {
+            model: self.model.clone(),
+            created_at: String::new(),
+            message,
+            done,
+            done_reason: if done {
+                self.finish_reason.clone()
+            } else {
+                None
+            },
+            total_duration: None,
+            load_duration: None,
+            prompt_eval_count: usage.map(|usage| usage.prompt_tokens),
+            prompt_eval_duration: None,
+            eval_count: usage.map(|usage| usage.completion_tokens),
+            eval_duration: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    #[serde(default)]
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
@@ -0,0 +1,92 @@
+use std::{fs, path::Path};
+
+use crate::ollama::ToolDefinition;
+
+const DEFAULT_TEMPLATE: &str = include_str!("prompts/default.txt");
+
+/// Templates tuned for specific model families, matched against the
+/// lowercased model name. The first match wins.
+const MODEL_TEMPLATES: [(&str, &str); 3] = [
+    ("qwen", include_str!("prompts/qwen.txt")),
+    ("llama", include_str!("prompts/llama.txt")),
+    ("mistral", include_str!("prompts/mistral.txt")),
+];
+
+/// Values substituted for `{{variable}}` placeholders in a template.
+#[derive(Debug)]
+pub struct TemplateContext {
+    pub model: String,
+    pub workspace_root: String,
+    pub os: String,
+    pub date: String,
+    pub tools: String,
+}
+
+impl TemplateContext {
+    pub fn new(model: &str, workspace: &Path, tools: &[ToolDefinition]) -> Self {
+        let workspace_root = fs::canonicalize(workspace)
+            .unwrap_or_else(|_| workspace.to_path_buf())
+            .to_string_lossy()
+            .to_string();
+
+        Self {
+            model: model.to_string(),
+            workspace_root,
+            os: std::env::consts::OS.to_string(),
+            date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            tools: describe_tools(tools),
+        }
+    }
+}
+
+/// The model specific templates rely on native tool calling. Models without
+/// it get the default template, which spells out the JSON format for calls.
+pub fn template_for(model: &str, native_tools: bool) -> &'static str {
+    if !native_tools {
+        return DEFAULT_TEMPLATE;
+    }
+    let model = model.to_lowercase();
+    MODEL_TEMPLATES
+        .iter()
+        .find(|(pattern, _)| model.contains(pattern))
+        .map(|(_, template)| *template)
+        .unwrap_or(DEFAULT_TEMPLATE)
+}
+
+/// Whether the model is asked to answer with JSON objects, which is what the
+/// default template spells out. The model specific templates ask for
+/// markdown, and custom templates are up to whoever wrote them.
+pub fn asks_for_json(template: &str) -> bool {
+    template == DEFAULT_TEMPLATE
+}
+
+pub fn render(template: &str, context: &TemplateContext) -> String {
+    template
+        .replace("{{model}}", &context.model)
+        .replace("{{workspace_root}}", &context.workspace_root)
+        .replace("{{os}}", &context.os)
+        .replace("{{date}}", &context.date)
+        .replace("{{tools}}", &context.tools)
+}
+
+fn describe_tools(definitions: &[ToolDefinition]) -> String {
+    definitions
+        .iter()
+        .map(|tool| {
+            let function = &tool.function;
+            let params: Vec<&str> = function
+                .parameters
+                .as_ref()
+                .and_then(|p| p["properties"].as_object())
+                .map(|props| props.keys().map(String::as_str).collect())
+                .unwrap_or_default();
+            format!(
+                "- {}({}): {}",
+                function.name,
+                params.join(", "),
+                function.description
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
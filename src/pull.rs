@@ -0,0 +1,238 @@
+use std::io::{IsTerminal, Write};
+
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveToColumn,
+    terminal::{Clear, ClearType},
+};
+use futures::{Stream, StreamExt};
+
+use ollama_code::ollama::{OllamaClient, OllamaError, PullProgress};
+
+const BAR_WIDTH: usize = 30;
+
+/// Pulls a model, drawing a progress bar for each layer. When stdout isn't
+/// a terminal, only the status changes are printed.
+#[tracing::instrument(skip(client))]
+pub async fn pull(client: &OllamaClient, model: &str) -> Result<(), OllamaError> {
+    let progress = client.pull(model).await?;
+    let interactive = std::io::stdout().is_terminal();
+    show_progress(
+        progress,
+        &mut ProgressDisplay::new(std::io::stdout(), interactive),
+    )
+    .await
+}
+
+/// Draws the updates until the stream ends. An error in the stream ends the
+/// current line before it's returned.
+async fn show_progress<W: Write>(
+    progress: impl Stream<Item = Result<PullProgress, OllamaError>>,
+    display: &mut ProgressDisplay<W>,
+) -> Result<(), OllamaError> {
+    let mut progress = std::pin::pin!(progress);
+    while let Some(update) = progress.next().await {
+        match update {
+            Ok(update) => display.update(&update)?,
+            Err(err) => {
+                display.finish()?;
+                return Err(err);
+            }
+        }
+    }
+    display.finish()?;
+
+    Ok(())
+}
+
+struct ProgressDisplay<W> {
+    out: W,
+    interactive: bool,
+    last_status: Option<String>,
+}
+
+impl<W: Write> ProgressDisplay<W> {
+    fn new(out: W, interactive: bool) -> Self {
+        Self {
+            out,
+            interactive,
+            last_status: None,
+        }
+    }
+
+    fn update(&mut self, update: &PullProgress) -> Result<(), std::io::Error> {
+        let stdout = &mut self.out;
+        let status_changed = self.last_status.as_deref() != Some(update.status.as_str());
+
+        if status_changed && self.last_status.is_some() {
+            // Keep the finished line and continue on a new one
+            writeln!(stdout)?;
+        }
+        self.last_status = Some(update.status.clone());
+
+        if !self.interactive {
+            if status_changed {
+                write!(stdout, "{}", update.status)?;
+            }
+            return stdout.flush();
+        }
+
+        stdout
+            .queue(MoveToColumn(0))?
+            .queue(Clear(ClearType::CurrentLine))?;
+        match (update.total, update.completed) {
+            (Some(total), completed) if total > 0 => {
+                let completed = completed.unwrap_or(0).min(total);
+                write!(
+                    stdout,
+                    "{}  {}  {:>3}%  {} / {}",
+                    short_status(&update.status),
+                    bar(completed, total),
+                    completed * 100 / total,
+                    human_size(completed),
+                    human_size(total)
+                )?;
+            }
+            _ => write!(stdout, "{}", update.status)?,
+        }
+        stdout.flush()
+    }
+
+    fn finish(&mut self) -> Result<(), std::io::Error> {
+        if self.last_status.take().is_some() {
+            writeln!(self.out)?;
+        }
+        self.out.flush()
+    }
+}
+
+/// "pulling sha256:8eeb52dfb3bb..." is shortened to "pulling 8eeb52dfb3bb"
+fn short_status(status: &str) -> String {
+    match status.split_once(' ') {
+        Some((verb, digest)) => {
+            let digest = digest.trim_start_matches("sha256:");
+            let digest: String = digest.chars().take(12).collect();
+            format!("{} {}", verb, digest)
+        }
+        None => status.to_string(),
+    }
+}
+
+fn bar(completed: u64, total: u64) -> String {
+    let filled = (completed as f64 / total as f64 * BAR_WIDTH as f64) as usize;
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PULL: &str = r#"{"status":"pulling manifest"}
+{"status":"pulling sha256:8eeb52dfb3bb9aef","digest":"sha256:8eeb52dfb3bb9aef","total":4000000000,"completed":0}
+{"status":"pulling sha256:8eeb52dfb3bb9aef","digest":"sha256:8eeb52dfb3bb9aef","total":4000000000,"completed":1000000000}
+{"status":"pulling sha256:8eeb52dfb3bb9aef","digest":"sha256:8eeb52dfb3bb9aef","total":4000000000,"completed":4000000000}
+{"status":"verifying sha256 digest"}
+{"status":"writing manifest"}
+{"status":"success"}
+"#;
+
+    /// Parses the lines like the client does, `{"error":…}` lines included
+    fn updates(ndjson: &str) -> impl Stream<Item = Result<PullProgress, OllamaError>> {
+        let updates: Vec<_> = ndjson
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                match value["error"].as_str() {
+                    Some(message) => Err(OllamaError::StreamedError(message.to_string())),
+                    None => Ok(serde_json::from_value(value).unwrap()),
+                }
+            })
+            .collect();
+        futures::stream::iter(updates)
+    }
+
+    async fn display(ndjson: &str, interactive: bool) -> (Result<(), OllamaError>, String) {
+        let mut display = ProgressDisplay::new(Vec::new(), interactive);
+        let result = show_progress(updates(ndjson), &mut display).await;
+        (result, String::from_utf8(display.out).unwrap())
+    }
+
+    #[tokio::test]
+    async fn prints_each_status_once() {
+        let (result, output) = display(PULL, false).await;
+
+        result.unwrap();
+        assert_eq!(
+            output,
+            "pulling manifest\n\
+             pulling sha256:8eeb52dfb3bb9aef\n\
+             verifying sha256 digest\n\
+             writing manifest\n\
+             success\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn draws_progress_bars() {
+        let (result, output) = display(PULL, true).await;
+
+        result.unwrap();
+        assert!(output.contains(&format!(
+            "pulling 8eeb52dfb3bb  [{}]    0%  0 B / 4.0 GB",
+            "-".repeat(BAR_WIDTH)
+        )));
+        assert!(output.contains(&format!(
+            "pulling 8eeb52dfb3bb  [{}{}]   25%  1.0 GB / 4.0 GB",
+            "#".repeat(7),
+            "-".repeat(23)
+        )));
+        assert!(output.contains(&format!(
+            "pulling 8eeb52dfb3bb  [{}]  100%  4.0 GB / 4.0 GB\n",
+            "#".repeat(BAR_WIDTH)
+        )));
+        assert!(output.ends_with("success\n"));
+    }
+
+    #[tokio::test]
+    async fn stops_at_errors() {
+        let pull = r#"{"status":"pulling manifest"}
+{"status":"pulling sha256:8eeb52dfb3bb9aef","total":4000000000,"completed":1000000000}
+{"error":"max retries exceeded: unexpected EOF"}
+{"status":"success"}
+"#;
+
+        let (result, output) = display(pull, false).await;
+
+        assert!(
+            matches!(&result, Err(OllamaError::StreamedError(message)) if message == "max retries exceeded: unexpected EOF"),
+            "{:?}",
+            result
+        );
+        assert_eq!(
+            output,
+            "pulling manifest\npulling sha256:8eeb52dfb3bb9aef\n"
+        );
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(human_size(999), "999 B");
+        assert_eq!(human_size(1_500_000), "1.5 MB");
+        assert_eq!(short_status("success"), "success");
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use futures::{FutureExt, StreamExt, future::BoxFuture, stream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{self, ChatBackend, ChatStream},
+    ollama::{ChatRequest, ChatResponse, OllamaError},
+};
+
+/// One request and what came back. Recordings are JSON Lines files with
+/// one exchange per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub request: ChatRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<ChatResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the error was one the assistant retries, so the replay
+    /// retries at the same points as the recorded session
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub transient: bool,
+}
+
+impl Exchange {
+    fn new(request: &ChatRequest, result: Result<&ChatResponse, &OllamaError>) -> Self {
+        Self {
+            request: request.clone(),
+            response: result.ok().cloned(),
+            error: result.err().map(|err| err.to_string()),
+            transient: result.is_err_and(OllamaError::is_transient),
+        }
+    }
+}
+
+type RecordingFile = Arc<Mutex<BufWriter<File>>>;
+
+/// Passes requests on to another backend and writes every exchange to a
+/// file, including the attempts that failed and were retried. Responses are
+/// passed on as they stream in and written once they are complete. Requests
+/// that are abandoned before that, e.g. cancelled ones, aren't recorded.
+pub struct Recorder {
+    inner: Box<dyn ChatBackend>,
+    file: RecordingFile,
+}
+
+impl Recorder {
+    pub fn new(inner: Box<dyn ChatBackend>, path: &Path) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            inner,
+            file: Arc::new(Mutex::new(BufWriter::new(File::create(path)?))),
+        })
+    }
+}
+
+fn write(file: &RecordingFile, exchange: &Exchange) -> Result<(), OllamaError> {
+    let mut file = file.lock().unwrap();
+    serde_json::to_writer(&mut *file, exchange)?;
+    writeln!(file)?;
+    // Flush right away, the recording is most useful when things crash
+    file.flush()?;
+    Ok(())
+}
+
+/// The chunks seen so far, until the stream ends
+struct Tee {
+    stream: ChatStream,
+    chunks: Vec<ChatResponse>,
+    request: ChatRequest,
+    file: RecordingFile,
+}
+
+impl ChatBackend for Recorder {
+    fn describe(&self) -> String {
+        format!("{} (recording)", self.inner.describe())
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+    ) -> BoxFuture<'a, Result<ChatStream, OllamaError>> {
+        async move {
+            let stream = match self.inner.chat_stream(request).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    write(&self.file, &Exchange::new(request, Err(&err)))?;
+                    return Err(err);
+                }
+            };
+
+            let tee = Tee {
+                stream,
+                chunks: Vec::new(),
+                request: request.clone(),
+                file: self.file.clone(),
+            };
+            let recorded = stream::unfold(Some(tee), |state| async move {
+                let mut tee = state?;
+                match tee.stream.next().await {
+                    Some(Ok(chunk)) => {
+                        tee.chunks.push(chunk.clone());
+                        Some((Ok(chunk), Some(tee)))
+                    }
+                    Some(Err(err)) => {
+                        let written = write(&tee.file, &Exchange::new(&tee.request, Err(&err)));
+                        Some((written.and(Err(err)), None))
+                    }
+                    None => {
+                        let chunks = stream::iter(tee.chunks.into_iter().map(Ok)).boxed();
+                        let result = backend::collect(chunks).await;
+                        let written =
+                            write(&tee.file, &Exchange::new(&tee.request, result.as_ref()));
+                        // Tell the assistant when the recording is incomplete
+                        match (written, result) {
+                            (Err(err), _) | (Ok(()), Err(err)) => Some((Err(err), None)),
+                            (Ok(()), Ok(_)) => None,
+                        }
+                    }
+                }
+            });
+            Ok(recorded.boxed())
+        }
+        .boxed()
+    }
+}
+
+/// Answers requests with the responses of a recording, in order, without
+/// talking to any server. Tools still run for real, so a replay also checks
+/// that they produce the same results: a request that differs from the
+/// recorded one fails.
+#[derive(Debug)]
+pub struct Replayer {
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl Replayer {
+    pub fn load(path: &Path) -> Result<Self, OllamaError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut exchanges = VecDeque::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange = serde_json::from_str(&line).map_err(|err| {
+                OllamaError::ReplayError(format!("{}:{}: {}", path.display(), number + 1, err))
+            })?;
+            exchanges.push_back(exchange);
+        }
+        Ok(Self {
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Whether the recorded session sent tool definitions to the model
+    pub fn native_tools(&self) -> bool {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .front()
+            .is_none_or(|exchange| !exchange.request.tools.is_empty())
+    }
+}
+
+impl ChatBackend for Replayer {
+    fn describe(&self) -> String {
+        "replay".to_string()
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+    ) -> BoxFuture<'a, Result<ChatStream, OllamaError>> {
+        async move {
+            let exchange = self.exchanges.lock().unwrap().pop_front().ok_or_else(|| {
+                OllamaError::ReplayError("the recording has no more responses".into())
+            })?;
+
+            // A different request means the run has diverged from the
+            // recording, and the recorded responses no longer apply. The
+            // system prompt is left out, it contains the date.
+            let recorded = exchange.request.messages.get(1..).unwrap_or_default();
+            let actual = request.messages.get(1..).unwrap_or_default();
+            if recorded != actual {
+                let position = recorded
+                    .iter()
+                    .zip(actual)
+                    .position(|(recorded, actual)| recorded != actual)
+                    .unwrap_or(recorded.len().min(actual.len()));
+                return Err(OllamaError::ReplayError(format!(
+                    "the request differs from the recording at message {} of {} (recorded {})",
+                    position + 2,
+                    request.messages.len(),
+                    exchange.request.messages.len()
+                )));
+            }
+
+            match (exchange.response, exchange.error) {
+                (Some(response), _) => Ok(stream::once(async { Ok(response) }).boxed()),
+                // Stands in for whatever made the server unavailable, so the
+                // assistant retries like it did while recording
+                (None, Some(error)) if exchange.transient => {
+                    Err(OllamaError::IoError(std::io::Error::other(error)))
+                }
+                (None, Some(error)) => Err(OllamaError::ReplayError(error)),
+                (None, None) => Err(OllamaError::ReplayError(
+                    "recorded exchange has neither a response nor an error".into(),
+                )),
+            }
+        }
+        .boxed()
+    }
+}
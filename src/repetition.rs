@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use crate::ollama::ToolCall;
+
+/// After this many rounds of tool calls without a single new call, the model
+/// is considered stuck and has to answer.
+const STAGNATION_LIMIT: usize = 2;
+
+/// Keeps track of the tool calls made while answering one question, so that
+/// a model calling the same tools over and over can be caught.
+#[derive(Debug, Default)]
+pub struct RepetitionTracker {
+    /// Results of read-only calls, until something changes the workspace
+    results: HashMap<String, String>,
+    repeated_calls: usize,
+    stale_rounds: usize,
+}
+
+impl RepetitionTracker {
+    /// The earlier result of an identical call, with a note for the model
+    pub fn repeated(&mut self, call: &ToolCall) -> Option<String> {
+        let result = self.results.get(&key(call))?;
+        self.repeated_calls += 1;
+        tracing::warn!(tool = call.function.name, "repeated tool call");
+
+        Some(format!(
+            "{}\n\nNOTE: You already called {} with exactly these arguments, this is the same result as before. \
+            Don't repeat calls. Use the results you have, try a different tool or file, or answer the question.",
+            result, call.function.name
+        ))
+    }
+
+    /// Only successful read-only calls should be recorded. Anything else
+    /// has to run again when the model repeats it.
+    pub fn record(&mut self, call: &ToolCall, result: &str) {
+        self.results.insert(key(call), result.to_string());
+    }
+
+    /// Forgets the recorded results after a call that might have changed
+    /// what they would return
+    pub fn forget_results(&mut self) {
+        self.results.clear();
+    }
+
+    /// Ends a round of tool calls. Returns true when the model has been going
+    /// in circles for too long.
+    pub fn end_round(&mut self, new_calls: usize) -> bool {
+        if new_calls == 0 {
+            self.stale_rounds += 1;
+        } else {
+            self.stale_rounds = 0;
+        }
+        self.stale_rounds >= STAGNATION_LIMIT
+    }
+
+    pub fn repeated_calls(&self) -> usize {
+        self.repeated_calls
+    }
+}
+
+/// Arguments are compared as JSON. Object keys are sorted, so the order the
+/// model wrote them in doesn't matter.
+fn key(call: &ToolCall) -> String {
+    format!("{}:{}", call.function.name, call.function.arguments)
+}
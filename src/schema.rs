@@ -0,0 +1,206 @@
+use serde_json::Value;
+
+/// Validates a value against the subset of JSON Schema we use for tool
+/// parameters: `type`, `properties`, `required`, `additionalProperties: false`,
+/// `items` and `enum`. Returns one message per problem found, in a form the
+/// model can act on.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "arguments", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, value: &Value, location: &str, errors: &mut Vec<String>) {
+    if let Some(expected) = schema["type"].as_str()
+        && !has_type(value, expected)
+    {
+        errors.push(format!(
+            "{}: expected {}, got {}",
+            location,
+            expected,
+            type_name(value)
+        ));
+        return;
+    }
+
+    if let Some(allowed) = schema["enum"].as_array()
+        && !allowed.contains(value)
+    {
+        let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+        errors.push(format!(
+            "{}: must be one of {}, got {}",
+            location,
+            allowed.join(", "),
+            value
+        ));
+    }
+
+    if let Some(object) = value.as_object() {
+        let properties = schema["properties"].as_object();
+
+        for required in schema["required"].as_array().into_iter().flatten() {
+            if let Some(key) = required.as_str()
+                && !object.contains_key(key)
+            {
+                errors.push(format!("{}: missing required property '{}'", location, key));
+            }
+        }
+
+        for (key, property_value) in object {
+            match properties.and_then(|p| p.get(key)) {
+                Some(property_schema) => validate_at(
+                    property_schema,
+                    property_value,
+                    &format!("{}.{}", location, key),
+                    errors,
+                ),
+                None if schema["additionalProperties"] == Value::Bool(false) => {
+                    let known: Vec<&str> = properties
+                        .map(|p| p.keys().map(String::as_str).collect())
+                        .unwrap_or_default();
+                    errors.push(format!(
+                        "{}: unknown property '{}', expected one of: {}",
+                        location,
+                        key,
+                        known.join(", ")
+                    ));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate_at(
+                item_schema,
+                item,
+                &format!("{}[{}]", location, index),
+                errors,
+            );
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "mode": { "type": "string", "enum": ["read", "write"] },
+                "lines": { "type": "array", "items": { "type": "integer" } },
+                "range": {
+                    "type": "object",
+                    "properties": { "start": { "type": "integer" } },
+                    "required": ["start"],
+                },
+            },
+            "required": ["path"],
+            "additionalProperties": false,
+        })
+    }
+
+    #[test]
+    fn accepts_valid_arguments() {
+        let value = json!({
+            "path": "src/main.rs",
+            "mode": "read",
+            "lines": [1, 2],
+            "range": { "start": 3 },
+        });
+
+        assert!(validate(&schema(), &value).is_empty());
+    }
+
+    #[test]
+    fn reports_type_mismatches() {
+        assert_eq!(
+            validate(&schema(), &json!({ "path": 42 })),
+            ["arguments.path: expected string, got number"]
+        );
+        assert_eq!(
+            validate(&schema(), &json!("src/main.rs")),
+            ["arguments: expected object, got string"]
+        );
+    }
+
+    #[test]
+    fn reports_values_outside_the_enum() {
+        assert_eq!(
+            validate(&schema(), &json!({ "path": "a", "mode": "delete" })),
+            [r#"arguments.mode: must be one of "read", "write", got "delete""#]
+        );
+    }
+
+    #[test]
+    fn reports_missing_properties() {
+        assert_eq!(
+            validate(&schema(), &json!({})),
+            ["arguments: missing required property 'path'"]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_properties() {
+        assert_eq!(
+            validate(&schema(), &json!({ "path": "a", "file": "b" })),
+            ["arguments: unknown property 'file', expected one of: lines, mode, path, range"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_items() {
+        assert_eq!(
+            validate(&schema(), &json!({ "path": "a", "lines": [1, "two", 3.5] })),
+            [
+                "arguments.lines[1]: expected integer, got string",
+                "arguments.lines[2]: expected integer, got number",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_problems_in_nested_objects() {
+        assert_eq!(
+            validate(&schema(), &json!({ "path": "a", "range": { "end": 4 } })),
+            ["arguments.range: missing required property 'start'"]
+        );
+        assert_eq!(
+            validate(
+                &schema(),
+                &json!({ "path": "a", "range": { "start": "1" } })
+            ),
+            ["arguments.range.start: expected integer, got string"]
+        );
+    }
+}
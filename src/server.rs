@@ -0,0 +1,372 @@
+//! Drives assistants over JSON-RPC 2.0, one message per line on stdin and
+//! stdout, so editors can embed the same agent loop as the REPL.
+//!
+//! Requests from the client:
+//!
+//! - `session/create` `{ workspace? }`: starts a session, returns
+//!   `{ sessionId, model, workspace }`
+//! - `session/prompt` `{ sessionId, prompt }`: asks a question, returns
+//!   `{ answer, usage }` once it's answered. A session answers one prompt at
+//!   a time.
+//! - `session/approve` `{ sessionId, callId, approved }`: decides about a
+//!   tool call that needs approval
+//! - `session/cancel` `{ sessionId }`: cancels the running prompt, which then
+//!   fails with code -32800
+//! - `session/close` `{ sessionId }`
+//! - `session/list`: returns `{ sessions: [{ sessionId, model, workspace,
+//!   busy }] }`
+//!
+//! Notifications from the server:
+//!
+//! - `session/event` `{ sessionId, event }`: every `AssistantEvent` while a
+//!   prompt runs
+//! - `session/approvalRequested` `{ sessionId, callId, name, arguments }`:
+//!   the call waits until the client answers with `session/approve`
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    assistant::{Assistant, AssistantBuilder, AssistantError},
+    events::AssistantEvent,
+    ollama::ToolCall,
+};
+
+/// Sets up the assistant for a new session in the given workspace. The
+/// server adds its own event handler and approval callback.
+pub type AssistantFactory = Box<dyn Fn(&Path) -> AssistantBuilder + Send + Sync>;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The question failed, the message says why
+const PROMPT_FAILED: i64 = -32000;
+const SESSION_BUSY: i64 = -32001;
+/// Same code as the Language Server Protocol uses
+const REQUEST_CANCELLED: i64 = -32800;
+
+type RpcError = (i64, String);
+type Approvals = Arc<Mutex<HashMap<String, oneshot::Sender<bool>>>>;
+
+/// Answers requests from `reader` on `writer` until the client closes its
+/// side. Sessions without a workspace work in `workspace`.
+pub async fn serve(
+    factory: AssistantFactory,
+    workspace: &Path,
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin + Send + 'static,
+) -> Result<(), std::io::Error> {
+    let (out, mut outgoing) = mpsc::unbounded_channel::<Value>();
+    let writing = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            let mut line = message.to_string();
+            line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+            writer.flush().await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    let server = Server {
+        factory,
+        workspace: workspace.to_path_buf(),
+        sessions: Mutex::new(BTreeMap::new()),
+        next_id: AtomicUsize::new(1),
+        out,
+    };
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        server.handle(&line);
+    }
+
+    // Nobody is listening anymore
+    for session in std::mem::take(&mut *server.sessions.lock().unwrap()).into_values() {
+        session.cancel();
+    }
+    drop(server);
+    writing.await.unwrap_or(Ok(()))
+}
+
+struct Server {
+    factory: AssistantFactory,
+    workspace: PathBuf,
+    sessions: Mutex<BTreeMap<String, Arc<ServerSession>>>,
+    next_id: AtomicUsize,
+    out: mpsc::UnboundedSender<Value>,
+}
+
+struct ServerSession {
+    id: String,
+    /// Sessions are listed in the order they were created
+    number: usize,
+    model: String,
+    workspace: PathBuf,
+    assistant: Arc<tokio::sync::Mutex<Assistant>>,
+    /// Cancels the running prompt
+    running: Mutex<Option<CancellationToken>>,
+    approvals: Approvals,
+}
+
+impl ServerSession {
+    fn cancel(&self) -> bool {
+        match self.running.lock().unwrap().as_ref() {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateParams {
+    #[serde(default)]
+    workspace: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionParams {
+    session_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptParams {
+    session_id: String,
+    prompt: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApproveParams {
+    session_id: String,
+    call_id: String,
+    approved: bool,
+}
+
+impl Server {
+    fn handle(&self, line: &str) {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(err) => {
+                let error = (PARSE_ERROR, format!("Parse error: {}", err));
+                return self.respond(&Value::Null, Err(error));
+            }
+        };
+        // Notifications from the client don't need an answer
+        let Some(id) = message.get("id").cloned() else {
+            return;
+        };
+        let Some(method) = message["method"].as_str() else {
+            let error = (INVALID_REQUEST, "Missing method".to_string());
+            return self.respond(&id, Err(error));
+        };
+
+        let params = message.get("params").cloned().unwrap_or(json!({}));
+        let result = match method {
+            "session/create" => parse(params).and_then(|params| self.create(params)),
+            "session/prompt" => {
+                match parse(params).and_then(|params| self.prompt(id.clone(), params)) {
+                    // Answered when the prompt is done
+                    Ok(()) => return,
+                    Err(err) => Err(err),
+                }
+            }
+            "session/approve" => parse(params).and_then(|params| self.approve(params)),
+            "session/cancel" => parse(params).and_then(|params: SessionParams| {
+                let cancelled = self.session(&params.session_id)?.cancel();
+                Ok(json!({ "cancelled": cancelled }))
+            }),
+            "session/close" => parse(params).and_then(|params: SessionParams| {
+                let session = self.session(&params.session_id)?;
+                session.cancel();
+                self.sessions.lock().unwrap().remove(&session.id);
+                Ok(json!({}))
+            }),
+            "session/list" => Ok(self.list()),
+            method => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+        };
+        self.respond(&id, result);
+    }
+
+    fn respond(&self, id: &Value, result: Result<Value, RpcError>) {
+        let _ = self.out.send(response(id, result));
+    }
+
+    fn session(&self, id: &str) -> Result<Arc<ServerSession>, RpcError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown session: {}", id)))
+    }
+
+    fn create(&self, params: CreateParams) -> Result<Value, RpcError> {
+        let workspace = params.workspace.unwrap_or_else(|| self.workspace.clone());
+        if !workspace.is_dir() {
+            return Err((
+                INVALID_PARAMS,
+                format!("Workspace {} is not a directory", workspace.display()),
+            ));
+        }
+        let number = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let id = format!("session-{}", number);
+        let approvals = Approvals::default();
+
+        let events = {
+            let out = self.out.clone();
+            let id = id.clone();
+            Box::new(move |event: &AssistantEvent| {
+                let _ = out.send(notification(
+                    "session/event",
+                    json!({ "sessionId": id, "event": event }),
+                ));
+            })
+        };
+        let approve = {
+            let out = self.out.clone();
+            let id = id.clone();
+            let approvals = approvals.clone();
+            Box::new(move |call: &ToolCall| {
+                let call_id = call.id.clone().unwrap_or_default();
+                let (sender, receiver) = oneshot::channel();
+                approvals.lock().unwrap().insert(call_id.clone(), sender);
+                let _ = out.send(notification(
+                    "session/approvalRequested",
+                    json!({
+                        "sessionId": id,
+                        "callId": call_id,
+                        "name": call.function.name,
+                        "arguments": call.function.arguments,
+                    }),
+                ));
+                // A closed session denies everything
+                Box::pin(async move { receiver.await.unwrap_or(false) }) as BoxFuture<'static, bool>
+            })
+        };
+        let assistant = (self.factory)(&workspace)
+            .with_event_handler(events)
+            .with_approval_callback(approve)
+            .build();
+
+        let session = Arc::new(ServerSession {
+            id: id.clone(),
+            number,
+            model: assistant.model().to_string(),
+            workspace: std::fs::canonicalize(&workspace).unwrap_or(workspace),
+            assistant: Arc::new(tokio::sync::Mutex::new(assistant)),
+            running: Mutex::new(None),
+            approvals,
+        });
+        let result = json!({
+            "sessionId": id,
+            "model": session.model,
+            "workspace": session.workspace,
+        });
+        self.sessions.lock().unwrap().insert(id, session);
+        Ok(result)
+    }
+
+    /// Runs the prompt in the background, so the client can approve tool
+    /// calls or cancel in the meantime
+    fn prompt(&self, request_id: Value, params: PromptParams) -> Result<(), RpcError> {
+        let session = self.session(&params.session_id)?;
+        let Ok(mut assistant) = session.assistant.clone().try_lock_owned() else {
+            return Err((
+                SESSION_BUSY,
+                format!("Session {} is busy with another prompt", session.id),
+            ));
+        };
+        let cancel = CancellationToken::new();
+        *session.running.lock().unwrap() = Some(cancel.clone());
+
+        let out = self.out.clone();
+        tokio::spawn(async move {
+            let result = assistant.ask(&params.prompt, &cancel).await;
+            *session.running.lock().unwrap() = None;
+            session.approvals.lock().unwrap().clear();
+
+            let result = match result {
+                Ok(answer) => Ok(json!({ "answer": answer, "usage": assistant.stats() })),
+                Err(AssistantError::Cancelled) => Err((REQUEST_CANCELLED, "Cancelled".to_string())),
+                Err(err) => Err((PROMPT_FAILED, err.to_string())),
+            };
+            // Free for the next prompt before the client hears about this one
+            drop(assistant);
+            let _ = out.send(response(&request_id, result));
+        });
+        Ok(())
+    }
+
+    fn approve(&self, params: ApproveParams) -> Result<Value, RpcError> {
+        let session = self.session(&params.session_id)?;
+        let Some(sender) = session.approvals.lock().unwrap().remove(&params.call_id) else {
+            return Err((
+                INVALID_PARAMS,
+                format!("No tool call {} waiting for approval", params.call_id),
+            ));
+        };
+        let _ = sender.send(params.approved);
+        Ok(json!({}))
+    }
+
+    fn list(&self) -> Value {
+        let mut sessions: Vec<Arc<ServerSession>> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+        sessions.sort_by_key(|session| session.number);
+        let sessions: Vec<Value> = sessions
+            .iter()
+            .map(|session| {
+                json!({
+                    "sessionId": session.id,
+                    "model": session.model,
+                    "workspace": session.workspace,
+                    "busy": session.running.lock().unwrap().is_some(),
+                })
+            })
+            .collect();
+        json!({ "sessions": sessions })
+    }
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|err| (INVALID_PARAMS, format!("Invalid params: {}", err)))
+}
+
+fn response(id: &Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ModelOptions, ollama::ChatMessage};
+
+/// A record of one REPL session, written after every turn. It contains
+/// everything needed to reproduce a run: the model, the options it started
+/// with, every option change along the way and the full conversation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub created_at: String,
+    pub workspace: PathBuf,
+    pub model: String,
+    pub options: ModelOptions,
+    pub option_changes: Vec<OptionChange>,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// An option changed with `/set`, taking effect from the message at
+/// `message_index` on.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OptionChange {
+    pub message_index: usize,
+    pub key: String,
+    pub value: String,
+}
+
+impl Session {
+    pub fn new(workspace: &Path, model: &str, options: &ModelOptions) -> Self {
+        let now = chrono::Local::now();
+        Self {
+            id: format!("{}-{}", now.format("%Y%m%d-%H%M%S"), std::process::id()),
+            created_at: now.to_rfc3339(),
+            workspace: fs::canonicalize(workspace).unwrap_or_else(|_| workspace.to_path_buf()),
+            model: model.to_string(),
+            options: options.clone(),
+            option_changes: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn record_option_change(&mut self, key: &str, value: &str) {
+        self.option_changes.push(OptionChange {
+            message_index: self.messages.len(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        sessions_dir().map(|dir| dir.join(format!("{}.json", self.id)))
+    }
+
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        let Some(path) = self.path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+pub fn sessions_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("ollama_code").join("sessions"))
+}
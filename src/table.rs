@@ -0,0 +1,45 @@
+/// Lines up the cells in columns separated by two spaces. Widths are counted
+/// in characters, so names with non-ASCII characters stay aligned.
+pub fn render<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.map(String::from);
+    std::iter::once(&header)
+        .chain(rows)
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_non_ascii_cells() {
+        let rows = [
+            ["café:7b".to_string(), "yes".to_string()],
+            ["llama3.1".to_string(), "".to_string()],
+        ];
+
+        assert_eq!(
+            render(["NAME", "TOOLS"], &rows),
+            "NAME      TOOLS\n\
+             café:7b   yes\n\
+             llama3.1"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use serde_json::{Deserializer, Value};
+
+use crate::ollama::{FunctionCall, ToolCall};
+
+/// Extracts tool calls that a model wrote into its message content instead
+/// of returning them as native tool calls. Small models do this a lot, in
+/// a variety of formats:
+///
+/// - plain JSON: `{"name": "read_file", "arguments": {"path": "README.md"}}`
+/// - the format from our own prompt: `{"tool_calls": [{"function": {...}}]}`
+/// - Hermes/Qwen tags: `<tool_call>{"name": ..., "arguments": ...}</tool_call>`
+/// - fenced code blocks, Llama's `<|python_tag|>` and Mistral's `[TOOL_CALLS]`
+///   prefixes, which all boil down to JSON somewhere in the text
+///
+/// Only calls to tools in `known_tools` are returned, so JSON that is part of
+/// a regular answer isn't mistaken for a tool call.
+pub fn parse_tool_calls(content: &str, known_tools: &[&str]) -> Vec<ToolCall> {
+    let mut calls = Vec::new();
+
+    // Tagged calls are unambiguous, prefer them when present
+    for tagged in tagged_sections(content, "<tool_call>", "</tool_call>") {
+        for value in json_values(tagged) {
+            collect_calls(&value, known_tools, &mut calls);
+        }
+    }
+    if !calls.is_empty() {
+        return calls;
+    }
+
+    for value in json_values(content) {
+        collect_calls(&value, known_tools, &mut calls);
+    }
+    calls
+}
+
+fn tagged_sections<'a>(content: &'a str, open: &str, close: &str) -> Vec<&'a str> {
+    let mut sections = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(open) {
+        let after = &rest[start + open.len()..];
+        // Models sometimes stop generating before the closing tag
+        let end = after.find(close).unwrap_or(after.len());
+        sections.push(&after[..end]);
+        rest = &after[end..];
+    }
+    sections
+}
+
+/// Finds all top level JSON objects and arrays in the text
+fn json_values(text: &str) -> Vec<Value> {
+    let mut values = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = text[offset..].find(['{', '[']).map(|i| i + offset) {
+        let mut stream = Deserializer::from_str(&text[start..]).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(value)) => {
+                values.push(value);
+                offset = start + stream.byte_offset();
+            }
+            _ => offset = start + 1,
+        }
+    }
+    values
+}
+
+fn collect_calls(value: &Value, known_tools: &[&str], calls: &mut Vec<ToolCall>) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect_calls(item, known_tools, calls);
+            }
+        }
+        Value::Object(object) => {
+            if let Some(tool_calls) = object.get("tool_calls") {
+                collect_calls(tool_calls, known_tools, calls);
+            } else if let Some(function) = object.get("function").filter(|f| f.is_object()) {
+                if let Some(call) = to_call(function, object.get("id"), known_tools) {
+                    calls.push(call);
+                }
+            } else if let Some(call) = to_call(value, object.get("id"), known_tools) {
+                calls.push(call);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn to_call(value: &Value, id: Option<&Value>, known_tools: &[&str]) -> Option<ToolCall> {
+    let name = value
+        .get("name")
+        .or_else(|| value.get("tool"))
+        .and_then(Value::as_str)?;
+    if !known_tools.contains(&name) {
+        return None;
+    }
+
+    let arguments = value
+        .get("arguments")
+        .or_else(|| value.get("parameters"))
+        .or_else(|| value.get("args"))
+        .cloned()
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    // OpenAI style calls carry the arguments as a JSON encoded string
+    let arguments = match arguments {
+        Value::String(encoded) => serde_json::from_str(&encoded).unwrap_or(Value::String(encoded)),
+        other => other,
+    };
+
+    Some(ToolCall {
+        id: id.and_then(Value::as_str).map(String::from),
+        function: FunctionCall {
+            name: name.to_string(),
+            arguments,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const TOOLS: &[&str] = &["read_file", "list_files"];
+
+    fn parse(content: &str) -> Vec<(String, Value)> {
+        parse_tool_calls(content, TOOLS)
+            .into_iter()
+            .map(|call| (call.function.name, call.function.arguments))
+            .collect()
+    }
+
+    #[test]
+    fn parses_fenced_json() {
+        let content = "Let me look.\n```json\n{\"name\": \"read_file\", \"arguments\": {\"path\": \"README.md\"}}\n```";
+
+        assert_eq!(
+            parse(content),
+            [("read_file".into(), json!({ "path": "README.md" }))]
+        );
+    }
+
+    #[test]
+    fn parses_the_prompt_format() {
+        let content = r#"{"tool_calls": [
+            {"id": "1", "function": {"name": "list_files", "arguments": {"path": "src"}}},
+            {"function": {"name": "read_file", "arguments": {"path": "src/main.rs"}}}
+        ]}"#;
+
+        let calls = parse_tool_calls(content, TOOLS);
+
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id.as_deref(), Some("1"));
+        assert_eq!(calls[0].function.name, "list_files");
+        assert_eq!(
+            calls[1].function.arguments,
+            json!({ "path": "src/main.rs" })
+        );
+    }
+
+    #[test]
+    fn parses_mistral_calls() {
+        let content = r#"[TOOL_CALLS] [{"name": "read_file", "arguments": {"path": "a.rs"}}, {"name": "read_file", "arguments": {"path": "b.rs"}}]"#;
+
+        assert_eq!(
+            parse(content),
+            [
+                ("read_file".into(), json!({ "path": "a.rs" })),
+                ("read_file".into(), json!({ "path": "b.rs" })),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_llama_calls() {
+        let content = r#"<|python_tag|>{"name": "list_files", "parameters": {"path": "."}}"#;
+
+        assert_eq!(
+            parse(content),
+            [("list_files".into(), json!({ "path": "." }))]
+        );
+    }
+
+    #[test]
+    fn prefers_tagged_calls() {
+        let content = r#"Example: {"name": "list_files", "arguments": {}}
+<tool_call>{"name": "read_file", "arguments": {"path": "Cargo.toml"}}"#;
+
+        assert_eq!(
+            parse(content),
+            [("read_file".into(), json!({ "path": "Cargo.toml" }))]
+        );
+    }
+
+    #[test]
+    fn decodes_arguments_given_as_a_string() {
+        let content = r#"{"name": "read_file", "arguments": "{\"path\": \"README.md\"}"}"#;
+        assert_eq!(
+            parse(content),
+            [("read_file".into(), json!({ "path": "README.md" }))]
+        );
+
+        // Kept as they are when they aren't JSON, validation reports them
+        let content = r#"{"name": "read_file", "arguments": "README.md"}"#;
+        assert_eq!(parse(content), [("read_file".into(), json!("README.md"))]);
+    }
+
+    #[test]
+    fn ignores_json_that_is_part_of_an_answer() {
+        let content = r#"The config looks like this:
+```json
+{"name": "ollama_code", "arguments": ["--help"], "tool_calls": []}
+```
+and the response is {"name": "answer", "value": 42}."#;
+
+        assert!(parse(content).is_empty());
+    }
+}
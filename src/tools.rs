@@ -1,46 +1,300 @@
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+
+use crate::{
+    ollama::{ToolCall, ToolDefinition},
+    schema,
+};
 use std::{
-    env,
+    fmt::Display,
     fs::{self, File},
     io::{BufRead, BufReader},
-    path::{self, PathBuf},
+    path::{self, Path, PathBuf},
+    sync::Arc,
 };
 
+/// A tool the model can call. Implement this to give the assistant tools of
+/// your own next to the built-in ones.
+pub trait Tool: Send + Sync {
+    /// Name, description and JSON schema of the arguments. The arguments are
+    /// validated against the schema before `call` sees them.
+    fn definition(&self) -> ToolDefinition;
+
+    /// Read-only tools can run concurrently with each other. Anything that
+    /// changes the workspace has to run on its own, in call order.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Calls of tools that need approval only run when the approval
+    /// callback allows them. Without a callback, they are denied.
+    fn needs_approval(&self) -> bool {
+        false
+    }
+
+    /// Runs the tool. A failed call is passed on to the model, so it should
+    /// say what went wrong in a way the model can act on.
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>>;
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Tool {
-    ReadDirectory(String),
-    ReadFile(String),
-    CurrentDir,
-    Grep { search_string: String, path: String },
+pub enum ToolError {
+    /// The call didn't work out, e.g. the file doesn't exist. The model
+    /// gets the message and can try something else.
+    Failed(String),
+    /// The tool itself is broken, e.g. it crashed. The question fails.
+    Broken(String),
+}
+
+impl From<String> for ToolError {
+    fn from(message: String) -> Self {
+        ToolError::Failed(message)
+    }
+}
+
+impl Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolError::Failed(message) | ToolError::Broken(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// The tools an assistant offers to the model
+#[derive(Clone, Default)]
+pub struct ToolSet {
+    tools: Vec<Arc<dyn Tool>>,
 }
 
-#[derive(Default, Debug)]
-pub struct Toolchain;
+impl std::fmt::Debug for ToolSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl ToolSet {
+    /// Adds a tool. A tool with the same name as an earlier one replaces it.
+    pub fn add(&mut self, tool: Arc<dyn Tool>) {
+        let name = tool.definition().function.name;
+        self.tools.retain(|t| t.definition().function.name != name);
+        self.tools.push(tool);
+    }
+
+    pub fn tools(&self) -> &[Arc<dyn Tool>] {
+        &self.tools
+    }
+
+    /// Tool definitions in the format the chat API expects
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|tool| tool.definition()).collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition().function.name)
+            .collect()
+    }
+
+    /// Looks up the tool for a call from the model and validates the
+    /// arguments against its schema. The error explains what is wrong, so
+    /// the model can fix the call and try again.
+    pub fn prepare(&self, call: &ToolCall) -> Result<PreparedCall, String> {
+        let name = &call.function.name;
+        let Some(tool) = self
+            .tools
+            .iter()
+            .find(|tool| tool.definition().function.name == *name)
+        else {
+            return Err(format!(
+                "Unknown tool '{}'. Available tools: {}",
+                name,
+                self.names().join(", ")
+            ));
+        };
+
+        // Calls without arguments sometimes come with `null` instead of `{}`
+        let arguments = match &call.function.arguments {
+            Value::Null => json!({}),
+            arguments => arguments.clone(),
+        };
+        if let Some(parameters) = &tool.definition().function.parameters {
+            let errors = schema::validate(parameters, &arguments);
+            if !errors.is_empty() {
+                return Err(format!(
+                    "Invalid arguments for '{}':\n- {}",
+                    name,
+                    errors.join("\n- ")
+                ));
+            }
+        }
+
+        Ok(PreparedCall {
+            tool: tool.clone(),
+            arguments,
+        })
+    }
+}
+
+/// A call with valid arguments, ready to run
+#[derive(Clone)]
+pub struct PreparedCall {
+    tool: Arc<dyn Tool>,
+    arguments: Value,
+}
+
+impl PreparedCall {
+    pub fn is_read_only(&self) -> bool {
+        self.tool.is_read_only()
+    }
+
+    pub fn needs_approval(&self) -> bool {
+        self.tool.needs_approval()
+    }
+
+    pub async fn run(&self) -> Result<String, ToolError> {
+        self.tool.call(&self.arguments).await
+    }
+}
+
+/// The built-in tools. They only read files inside the workspace root,
+/// relative paths are resolved against it.
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    root: PathBuf,
+}
+
+impl Default for Toolchain {
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
 
 impl Toolchain {
-    #[tracing::instrument(skip(self))]
-    fn normalize_path(&self, abs_or_relative_path: &str) -> Result<PathBuf, std::io::Error> {
-        let path = PathBuf::from(abs_or_relative_path);
-        std::fs::canonicalize(&path).and_then(|p| path::absolute(p))
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
     }
 
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The built-in tools, ready to add to a `ToolSet`
+    pub fn tools(&self) -> Vec<Arc<dyn Tool>> {
+        self.definitions()
+            .into_iter()
+            .map(|definition| {
+                Arc::new(Builtin {
+                    toolchain: self.clone(),
+                    definition,
+                }) as Arc<dyn Tool>
+            })
+            .collect()
+    }
+
+    /// Tool definitions in the format the chat API expects
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        vec![
+            ToolDefinition::function(
+                "list_directory",
+                "List files and directories",
+                Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Directory path"
+                        }
+                    },
+                    "required": ["path"]
+                })),
+            ),
+            ToolDefinition::function(
+                "read_file",
+                "Read file contents",
+                Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "File path"
+                        }
+                    },
+                    "required": ["path"]
+                })),
+            ),
+            ToolDefinition::function(
+                "pwd",
+                "Returns the full path of the workspace directory",
+                None,
+            ),
+            ToolDefinition::function(
+                "grep",
+                "Searches for a specific substring in a designated file",
+                Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "The file to grep through"
+                        },
+                        "search_pattern": {
+                            "type": "string",
+                            "description": "The search pattern"
+                        }
+                    },
+                    "required": ["path", "search_pattern"]
+                })),
+            ),
+        ]
+    }
+
+    /// Runs a built-in tool by name. The arguments have to be valid already.
     #[tracing::instrument(skip(self))]
-    pub fn call(&self, tool: Tool) -> Result<String, std::io::Error> {
-        match tool {
-            Tool::ReadDirectory(path) => self
-                .normalize_path(&path)
-                .and_then(|p| self.list_directory(&p)),
-            Tool::ReadFile(path) => self.normalize_path(&path).and_then(|p| self.read_file(&p)),
-            Tool::CurrentDir => self.pwd(),
-            Tool::Grep {
-                search_string,
-                path,
-            } => {
+    pub fn call(&self, name: &str, arguments: &Value) -> Result<String, String> {
+        let string = |key: &str| arguments[key].as_str().unwrap_or_default().to_string();
+        let (context, result) = match name {
+            "list_directory" => {
+                let path = string("path");
+                let result = self
+                    .normalize_path(&path)
+                    .and_then(|p| self.list_directory(&p));
+                (format!("Could not list directory '{}'", path), result)
+            }
+            "read_file" => {
+                let path = string("path");
+                let result = self.normalize_path(&path).and_then(|p| self.read_file(&p));
+                (format!("Could not read file '{}'", path), result)
+            }
+            "pwd" => ("Could not get current directory".to_string(), self.pwd()),
+            "grep" => {
+                let path = string("path");
                 let result = self
                     .normalize_path(&path)
-                    .and_then(|p| self.grep_streaming(&search_string, &p))?;
-                Ok(result)
+                    .and_then(|p| self.grep_streaming(&string("search_pattern"), &p));
+                (format!("Could not search in file '{}'", path), result)
             }
+            _ => return Err(format!("Tool '{}' is not implemented", name)),
+        };
+        result.map_err(|e| format!("{} - {}", context, e))
+    }
+
+    /// Resolves symlinks and `..`, so nothing outside the root can be reached
+    #[tracing::instrument(skip(self))]
+    fn normalize_path(&self, abs_or_relative_path: &str) -> Result<PathBuf, std::io::Error> {
+        // Absolute paths replace the root when joined
+        let path = self.root.join(abs_or_relative_path);
+        let path = std::fs::canonicalize(&path).and_then(path::absolute)?;
+        let root = std::fs::canonicalize(&self.root).and_then(path::absolute)?;
+        if !path.starts_with(&root) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("Outside of the workspace {}", root.display()),
+            ));
         }
+        Ok(path)
     }
 
     fn grep_streaming(
@@ -78,7 +332,7 @@ impl Toolchain {
 
     #[tracing::instrument(skip(self))]
     fn pwd(&self) -> Result<String, std::io::Error> {
-        env::current_dir().and_then(|path| Ok(path.to_string_lossy().to_string()))
+        fs::canonicalize(&self.root).map(|path| path.to_string_lossy().to_string())
     }
 
     #[tracing::instrument(skip(self))]
@@ -103,14 +357,13 @@ impl Toolchain {
                 let extension = path
                     .extension()
                     .map(|ext| format!(".{}", ext.to_string_lossy()))
-                    .unwrap_or_else(|| String::new());
+                    .unwrap_or_else(String::new);
                 files.push(format!("{}{}", name, {
-                    let display_extension = if extension.is_empty() {
+                    if extension.is_empty() {
                         String::new()
                     } else {
                         format!(" ({})", extension)
-                    };
-                    display_extension
+                    }
                 }));
             }
         }
@@ -124,7 +377,7 @@ impl Toolchain {
             for dir in dirs {
                 result.push_str(&format!("{}\n", dir));
             }
-            result.push_str("\n");
+            result.push('\n');
         }
 
         if !files.is_empty() {
@@ -133,7 +386,7 @@ impl Toolchain {
             for file in files {
                 result.push_str(&format!("{}\n", file));
             }
-            result.push_str("\n");
+            result.push('\n');
         }
 
         result
@@ -142,3 +395,33 @@ impl Toolchain {
         Ok(result)
     }
 }
+
+/// One of the built-in tools, as a `Tool`
+struct Builtin {
+    toolchain: Toolchain,
+    definition: ToolDefinition,
+}
+
+impl Tool for Builtin {
+    fn definition(&self) -> ToolDefinition {
+        self.definition.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    /// Tools do blocking file IO, so they run on the blocking pool. That way a
+    /// cancelled turn doesn't have to wait for them to finish.
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>> {
+        let toolchain = self.toolchain.clone();
+        let name = self.definition.function.name.clone();
+        let arguments = arguments.clone();
+        Box::pin(async move {
+            let result = tokio::task::spawn_blocking(move || toolchain.call(&name, &arguments))
+                .await
+                .map_err(|e| ToolError::Broken(e.to_string()))?;
+            Ok(result?)
+        })
+    }
+}
@@ -0,0 +1,1089 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use tokio_util::sync::CancellationToken;
+
+mod common;
+
+use common::{MockResponse, MockServer};
+use ollama_code::{
+    assistant::{Assistant, AssistantBuilder, AssistantError},
+    backend::{RetryPolicy, Timeouts},
+    config::ModelOptions,
+    events::{self, AssistantEvent},
+    ollama::{OllamaClient, OllamaError, ToolDefinition},
+    openai::OpenAiClient,
+    tools::{Tool, ToolError},
+};
+
+const CHAT: &str = "/api/chat";
+
+fn options() -> ModelOptions {
+    ModelOptions {
+        temperature: 0.5,
+        top_p: None,
+        top_k: None,
+        seed: Some(42),
+        num_ctx: 4096,
+        num_predict: None,
+        repeat_penalty: None,
+        stop: Vec::new(),
+        keep_alive: None,
+    }
+}
+
+fn builder(server: &MockServer) -> AssistantBuilder {
+    Assistant::builder("mock")
+        .with_system_prompt("You are a test")
+        .with_options(options())
+        .with_backend(Box::new(OllamaClient::new(server.url())))
+}
+
+fn assistant(server: &MockServer) -> Assistant {
+    builder(server).build()
+}
+
+async fn ask(assistant: &mut Assistant, question: &str) -> Result<String, AssistantError> {
+    assistant.ask(question, &CancellationToken::new()).await
+}
+
+fn last_message(request: &Value) -> &Value {
+    request["messages"].as_array().unwrap().last().unwrap()
+}
+
+fn tool_messages(request: &Value) -> Vec<&Value> {
+    request["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["role"] == "tool")
+        .collect()
+}
+
+#[tokio::test]
+async fn answers_without_tools() {
+    let server = MockServer::start().await;
+    server.enqueue(CHAT, MockResponse::answer("Hello"));
+    let mut assistant = assistant(&server);
+
+    assert_eq!(ask(&mut assistant, "Hi").await.unwrap(), "Hello");
+
+    let requests = server.requests(CHAT);
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["model"], "mock");
+    assert_eq!(requests[0]["options"]["seed"], 42);
+    assert!(!requests[0]["tools"].as_array().unwrap().is_empty());
+    assert_eq!(last_message(&requests[0])["content"], "Hi");
+    assert_eq!(assistant.conversation().len(), 3);
+}
+
+#[tokio::test]
+async fn constrains_answers_to_json_only_for_the_default_template() {
+    let server = MockServer::start().await;
+    let format = |model: &str, system_prompt: Option<&str>| {
+        let server = &server;
+        let model = model.to_string();
+        let system_prompt = system_prompt.map(String::from);
+        async move {
+            server.enqueue(CHAT, MockResponse::answer("Hello"));
+            let mut builder = Assistant::builder(model)
+                .with_options(options())
+                .with_backend(Box::new(OllamaClient::new(server.url())));
+            if let Some(system_prompt) = system_prompt {
+                builder = builder.with_system_prompt(system_prompt);
+            }
+            ask(&mut builder.build(), "Hi").await.unwrap();
+            server.requests(CHAT).last().unwrap().get("format").cloned()
+        }
+    };
+
+    assert_eq!(format("mock", None).await, Some(json!("json")));
+    // The model specific templates ask for markdown
+    assert_eq!(format("qwen2.5-coder:7b", None).await, None);
+    assert_eq!(format("mock", Some("Answer in haiku")).await, None);
+}
+
+#[tokio::test]
+async fn runs_tool_calls_and_sends_back_results() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("read_file", json!({ "path": "Cargo.toml" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("It's ollama_code"));
+    let mut assistant = assistant(&server);
+
+    let answer = ask(&mut assistant, "What's this project?").await.unwrap();
+
+    assert_eq!(answer, "It's ollama_code");
+    let requests = server.requests(CHAT);
+    assert_eq!(requests.len(), 2);
+    let result = last_message(&requests[1]);
+    assert_eq!(result["role"], "tool");
+    assert_eq!(result["tool_name"], "read_file");
+    assert!(result["content"].as_str().unwrap().contains("[package]"));
+}
+
+#[tokio::test]
+async fn parses_tool_calls_from_content() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::answer(
+                "Let me check.\n<tool_call>\n{\"name\": \"pwd\", \"arguments\": {}}\n</tool_call>",
+            ),
+        )
+        .enqueue(CHAT, MockResponse::answer("Done"));
+    let mut assistant = assistant(&server);
+
+    assert_eq!(ask(&mut assistant, "Where am I?").await.unwrap(), "Done");
+
+    let requests = server.requests(CHAT);
+    let result = last_message(&requests[1]);
+    assert_eq!(result["tool_name"], "pwd");
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(result["content"], cwd.to_string_lossy().as_ref());
+}
+
+#[tokio::test]
+async fn reports_invalid_arguments_to_the_model() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::tool_call("grep", json!({ "path": 3 })))
+        .enqueue(CHAT, MockResponse::tool_call("rm", json!({ "path": "/" })))
+        .enqueue(CHAT, MockResponse::answer("Sorry"));
+    let mut assistant = assistant(&server);
+
+    assert_eq!(ask(&mut assistant, "Search").await.unwrap(), "Sorry");
+
+    let requests = server.requests(CHAT);
+    let invalid = last_message(&requests[1])["content"].as_str().unwrap();
+    assert!(invalid.contains("missing required property 'search_pattern'"));
+    assert!(invalid.contains("arguments.path: expected string, got number"));
+    let unknown = last_message(&requests[2])["content"].as_str().unwrap();
+    assert!(unknown.starts_with("ERROR: Unknown tool 'rm'"));
+}
+
+#[tokio::test]
+async fn wraps_up_when_out_of_turns() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::tool_call("pwd", json!({})))
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("list_directory", json!({ "path": "." })),
+        )
+        .enqueue(CHAT, MockResponse::answer("Here's what I found"));
+    let mut assistant = builder(&server).with_max_turns(2).build();
+
+    let answer = ask(&mut assistant, "Explore").await.unwrap();
+
+    assert_eq!(answer, "Here's what I found");
+    let requests = server.requests(CHAT);
+    assert_eq!(requests.len(), 3);
+    assert!(requests[2].get("tools").is_none());
+    let wrap_up = last_message(&requests[2]);
+    assert_eq!(wrap_up["role"], "user");
+    assert!(
+        wrap_up["content"]
+            .as_str()
+            .unwrap()
+            .contains("Stop calling tools")
+    );
+}
+
+#[tokio::test]
+async fn grants_more_turns_through_the_callback() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::tool_call("pwd", json!({})))
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("list_directory", json!({ "path": "." })),
+        )
+        .enqueue(CHAT, MockResponse::answer("Done"));
+    let mut assistant = builder(&server)
+        .with_max_turns(1)
+        .with_turn_limit_callback(Box::new(|turns| {
+            Box::pin(async move { if turns < 3 { 1 } else { 0 } })
+        }))
+        .build();
+
+    assert_eq!(ask(&mut assistant, "Explore").await.unwrap(), "Done");
+
+    let requests = server.requests(CHAT);
+    assert_eq!(requests.len(), 3);
+    assert!(requests[2].get("tools").is_some());
+}
+
+#[tokio::test]
+async fn stops_models_repeating_the_same_call() {
+    let server = MockServer::start().await;
+    for _ in 0..3 {
+        server.enqueue(CHAT, MockResponse::tool_call("pwd", json!({})));
+    }
+    server.enqueue(CHAT, MockResponse::answer("Giving up"));
+    let mut assistant = assistant(&server);
+
+    assert_eq!(
+        ask(&mut assistant, "Where am I?").await.unwrap(),
+        "Giving up"
+    );
+
+    let requests = server.requests(CHAT);
+    assert_eq!(requests.len(), 4);
+    let repeated = last_message(&requests[2])["content"].as_str().unwrap();
+    assert!(repeated.contains("NOTE: You already called pwd"));
+    assert!(requests[3].get("tools").is_none());
+}
+
+#[tokio::test]
+async fn returns_results_of_concurrent_calls_in_call_order() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_calls(&[
+                ("read_file", json!({ "path": "Cargo.toml" })),
+                ("pwd", json!({})),
+                ("list_directory", json!({ "path": "src" })),
+            ]),
+        )
+        .enqueue(CHAT, MockResponse::answer("Done"));
+    let mut assistant = assistant(&server);
+
+    ask(&mut assistant, "Explore").await.unwrap();
+
+    let requests = server.requests(CHAT);
+    let results = tool_messages(&requests[1]);
+    let names: Vec<&str> = results
+        .iter()
+        .map(|m| m["tool_name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["read_file", "pwd", "list_directory"]);
+
+    let calls = &requests[1]["messages"][2]["tool_calls"];
+    for (index, result) in results.iter().enumerate() {
+        assert!(result["tool_call_id"].is_string());
+        assert_eq!(result["tool_call_id"], calls[index]["id"]);
+    }
+}
+
+/// One line of a streamed /api/chat response
+fn chunk(content: &str, done: bool) -> Value {
+    json!({
+        "model": "mock",
+        "message": { "role": "assistant", "content": content },
+        "done": done,
+    })
+}
+
+#[tokio::test]
+async fn merges_streamed_chunks() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        CHAT,
+        MockResponse::Chunks(vec![
+            chunk("Hel", false),
+            chunk("lo", false),
+            chunk("", true),
+        ]),
+    );
+    let mut assistant = assistant(&server);
+
+    assert_eq!(ask(&mut assistant, "Hi").await.unwrap(), "Hello");
+}
+
+#[tokio::test]
+async fn http_errors_roll_back_the_conversation() {
+    let server = MockServer::start().await;
+    server.enqueue(CHAT, MockResponse::error(500, "out of memory"));
+    let mut assistant = builder(&server).with_retry_policy(quick_retries(0)).build();
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    match error {
+        AssistantError::BackendUnavailable(OllamaError::HttpError { status, message }) => {
+            assert_eq!(status.as_u16(), 500);
+            assert_eq!(message, "out of memory");
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(assistant.conversation().len(), 1);
+}
+
+#[tokio::test]
+async fn empty_responses_are_invalid() {
+    let server = MockServer::start().await;
+    server.enqueue(CHAT, MockResponse::answer(" \n"));
+    let mut assistant = assistant(&server);
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    match error {
+        AssistantError::InvalidResponse(OllamaError::MalformedResponseError(message)) => {
+            assert_eq!(
+                message,
+                "the model returned neither an answer nor tool calls"
+            )
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(assistant.conversation().len(), 1);
+}
+
+#[tokio::test]
+async fn errors_in_the_stream_are_reported() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        CHAT,
+        MockResponse::Chunks(vec![json!({ "error": "model crashed" })]),
+    );
+    let mut assistant = assistant(&server);
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    assert!(
+        matches!(error, AssistantError::RequestFailed(OllamaError::StreamedError(ref message)) if message == "model crashed")
+    );
+}
+
+#[tokio::test]
+async fn missing_model_is_reported() {
+    let server = MockServer::start().await;
+    server.enqueue(CHAT, MockResponse::error(404, "model 'mock' not found"));
+    let mut assistant = assistant(&server);
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        AssistantError::ModelNotFound(ref model) if model == "mock"
+    ));
+}
+
+#[tokio::test]
+async fn malformed_responses_are_errors() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::Raw {
+                status: 200,
+                content_type: "application/x-ndjson",
+                body: "{\"model\": \"mock\", \"mess".into(),
+            },
+        )
+        .enqueue(CHAT, MockResponse::Json(200, json!({ "unexpected": true })));
+    let mut assistant = assistant(&server);
+
+    assert!(matches!(
+        ask(&mut assistant, "Hi").await.unwrap_err(),
+        AssistantError::InvalidResponse(_)
+    ));
+    assert!(matches!(
+        ask(&mut assistant, "Hi").await.unwrap_err(),
+        AssistantError::InvalidResponse(OllamaError::MalformedResponseError(_))
+    ));
+    assert_eq!(assistant.conversation().len(), 1);
+}
+
+#[tokio::test]
+async fn cancelling_abandons_the_turn() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        CHAT,
+        MockResponse::Delayed(
+            Duration::from_secs(5),
+            Box::new(MockResponse::answer("Too late")),
+        ),
+    );
+    let mut assistant = assistant(&server);
+    let cancel = CancellationToken::new();
+
+    let canceller = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        canceller.cancel();
+    });
+    let error = assistant.ask("Hi", &cancel).await.unwrap_err();
+
+    assert!(matches!(error, AssistantError::Cancelled));
+    assert_eq!(assistant.conversation().len(), 1);
+}
+
+/// An OpenAI style event stream, ending with `[DONE]`
+fn events(events: &[Value]) -> MockResponse {
+    MockResponse::Raw {
+        status: 200,
+        content_type: "text/event-stream",
+        body: events
+            .iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .chain(["data: [DONE]\n\n".to_string()])
+            .collect(),
+    }
+}
+
+#[tokio::test]
+async fn openai_backend_assembles_streamed_tool_calls() {
+    let server = MockServer::start().await;
+    let completions = "/v1/chat/completions";
+    server
+        .enqueue(
+            completions,
+            events(&[
+                json!({ "choices": [{ "delta": { "role": "assistant", "tool_calls": [
+                    { "index": 0, "id": "call_1", "type": "function",
+                      "function": { "name": "read_file", "arguments": "" } }
+                ] } }] }),
+                json!({ "choices": [{ "delta": { "tool_calls": [
+                    { "index": 0, "function": { "arguments": "{\"path\": " } }
+                ] } }] }),
+                json!({ "choices": [{ "delta": { "tool_calls": [
+                    { "index": 0, "function": { "arguments": "\"Cargo.toml\"}" } }
+                ] } }] }),
+                json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }),
+            ]),
+        )
+        .enqueue(
+            completions,
+            events(&[
+                json!({ "choices": [{ "delta": { "content": "It's " } }] }),
+                json!({ "choices": [{ "delta": { "content": "ollama_code" } }] }),
+                json!({ "choices": [{ "delta": {}, "finish_reason": "stop" }] }),
+            ]),
+        );
+    let backend = OpenAiClient::new(&format!("{}/v1", server.url()), None);
+    let mut assistant = Assistant::builder("mock")
+        .with_system_prompt("You are a test")
+        .with_options(options())
+        .with_backend(Box::new(backend))
+        .build();
+
+    let answer = ask(&mut assistant, "What's this project?").await.unwrap();
+
+    assert_eq!(answer, "It's ollama_code");
+    let requests = server.requests(completions);
+    assert_eq!(requests[0]["stream"], true);
+    assert_eq!(requests[0]["seed"], 42);
+    assert!(requests[0].get("num_ctx").is_none());
+    let call = &requests[1]["messages"][2]["tool_calls"][0];
+    assert_eq!(call["id"], "call_1");
+    assert_eq!(call["function"]["arguments"], "{\"path\":\"Cargo.toml\"}");
+    let result = last_message(&requests[1]);
+    assert_eq!(result["role"], "tool");
+    assert_eq!(result["tool_call_id"], "call_1");
+    assert!(result["content"].as_str().unwrap().contains("[package]"));
+}
+
+#[tokio::test]
+async fn openai_backend_reports_usage_and_calls_without_finish_reason() {
+    let server = MockServer::start().await;
+    let completions = "/v1/chat/completions";
+    let usage = |prompt: u64, completion: u64| {
+        json!({ "choices": [], "usage": {
+            "prompt_tokens": prompt, "completion_tokens": completion,
+            "total_tokens": prompt + completion
+        } })
+    };
+    server
+        .enqueue(
+            completions,
+            events(&[
+                json!({ "choices": [{ "delta": { "tool_calls": [
+                    { "index": 0, "id": "call_1", "type": "function",
+                      "function": { "name": "read_file",
+                                    "arguments": "{\"path\": \"Cargo.toml\"}" } }
+                ] } }] }),
+                usage(100, 20),
+            ]),
+        )
+        .enqueue(
+            completions,
+            events(&[
+                json!({ "choices": [{ "delta": { "content": "It's ollama_code" } }] }),
+                json!({ "choices": [{ "delta": {}, "finish_reason": "stop" }] }),
+                usage(300, 5),
+            ]),
+        );
+    let backend = OpenAiClient::new(&format!("{}/v1", server.url()), None);
+    let mut assistant = Assistant::builder("mock")
+        .with_system_prompt("You are a test")
+        .with_options(options())
+        .with_backend(Box::new(backend))
+        .build();
+
+    let answer = ask(&mut assistant, "What's this project?").await.unwrap();
+
+    assert_eq!(answer, "It's ollama_code");
+    let requests = server.requests(completions);
+    assert_eq!(requests[0]["stream_options"]["include_usage"], true);
+    assert_eq!(requests[1]["messages"][2]["tool_calls"][0]["id"], "call_1");
+    assert_eq!(assistant.stats().prompt_tokens, 400);
+    assert_eq!(assistant.stats().completion_tokens, 25);
+}
+
+#[tokio::test]
+async fn openai_backend_reports_errors_in_the_stream() {
+    let server = MockServer::start().await;
+    let completions = "/v1/chat/completions";
+    server.enqueue(
+        completions,
+        events(&[
+            json!({ "choices": [{ "delta": { "content": "It's " } }] }),
+            json!({ "error": {
+                "message": "the request exceeds the available context size",
+                "type": "exceed_context_size_error"
+            } }),
+            json!({ "choices": [{ "delta": { "content": "ollama_code" } }] }),
+        ]),
+    );
+    let backend = OpenAiClient::new(&format!("{}/v1", server.url()), None);
+    let mut assistant = Assistant::builder("mock")
+        .with_system_prompt("You are a test")
+        .with_options(options())
+        .with_backend(Box::new(backend))
+        .build();
+
+    let error = ask(&mut assistant, "What's this project?")
+        .await
+        .unwrap_err();
+
+    match error {
+        AssistantError::ContextOverflow(message) => {
+            assert_eq!(message, "the request exceeds the available context size")
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(assistant.conversation().len(), 1);
+}
+
+/// Looks up tickets, standing in for a tool an embedding program adds
+struct Tickets;
+
+impl Tool for Tickets {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::function(
+            "ticket",
+            "Looks up a ticket",
+            Some(json!({
+                "type": "object",
+                "properties": { "id": { "type": "integer" } },
+                "required": ["id"]
+            })),
+        )
+    }
+
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>> {
+        Box::pin(async move {
+            match arguments["id"].as_i64() {
+                Some(42) => Ok("Ticket 42: the build is broken".to_string()),
+                Some(0) => Err(ToolError::Broken("ticket server is down".into())),
+                id => Err(format!("No ticket {:?}", id).into()),
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn offers_and_calls_custom_tools() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_calls(&[
+                ("ticket", json!({ "id": 42 })),
+                ("ticket", json!({ "id": 7 })),
+                ("ticket", json!({ "id": "42" })),
+            ]),
+        )
+        .enqueue(CHAT, MockResponse::answer("The build is broken"));
+    let mut assistant = builder(&server).with_tool(Tickets).build();
+
+    assert_eq!(
+        ask(&mut assistant, "What's ticket 42?").await.unwrap(),
+        "The build is broken"
+    );
+
+    let requests = server.requests(CHAT);
+    let tools: Vec<&str> = requests[0]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| tool["function"]["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        tools,
+        ["list_directory", "read_file", "pwd", "grep", "ticket"]
+    );
+    let results = tool_messages(&requests[1]);
+    assert_eq!(results[0]["content"], "Ticket 42: the build is broken");
+    assert_eq!(results[1]["content"], "ERROR: No ticket Some(7)");
+    assert!(
+        results[2]["content"]
+            .as_str()
+            .unwrap()
+            .contains("arguments.id: expected integer, got string")
+    );
+    assert_eq!(assistant.stats().tool_errors, 2);
+}
+
+#[tokio::test]
+async fn emits_events_for_each_step() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_calls(&[
+                ("read_file", json!({ "path": "Cargo.toml" })),
+                ("nope", json!({})),
+            ]),
+        )
+        .enqueue(
+            CHAT,
+            MockResponse::Chunks(vec![chunk("Hel", false), chunk("lo", true)]),
+        );
+    let (handler, mut events) = events::channel();
+    let mut assistant = builder(&server).with_event_handler(handler).build();
+
+    ask(&mut assistant, "Hi").await.unwrap();
+    drop(assistant);
+
+    let mut received = Vec::new();
+    while let Some(event) = events.recv().await {
+        received.push(event);
+    }
+    let kinds: Vec<&str> = received
+        .iter()
+        .map(|event| match event {
+            AssistantEvent::TurnStarted { .. } => "turn_started",
+            AssistantEvent::ModelRequest { .. } => "model_request",
+            AssistantEvent::TokenDelta { .. } => "token_delta",
+            AssistantEvent::ToolCallStarted { .. } => "tool_call_started",
+            AssistantEvent::ToolCallFinished { .. } => "tool_call_finished",
+            AssistantEvent::TurnFinished { .. } => "turn_finished",
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            "turn_started",
+            "model_request",
+            "tool_call_started",
+            "tool_call_started",
+            "tool_call_finished",
+            "tool_call_finished",
+            "model_request",
+            "token_delta",
+            "token_delta",
+            "turn_finished",
+        ]
+    );
+
+    let finished: Vec<(&str, bool)> = received
+        .iter()
+        .filter_map(|event| match event {
+            AssistantEvent::ToolCallFinished { name, error, .. } => {
+                Some((name.as_str(), error.is_some()))
+            }
+            _ => None,
+        })
+        .collect();
+    assert!(finished.contains(&("read_file", false)));
+    assert!(finished.contains(&("nope", true)));
+    let AssistantEvent::TurnFinished { usage } = received.last().unwrap() else {
+        unreachable!()
+    };
+    assert_eq!(usage.requests, 2);
+    assert_eq!(usage.tool_errors, 1);
+}
+
+#[test]
+fn events_serialize_as_tagged_json() {
+    let event = AssistantEvent::ToolCallFinished {
+        id: Some("call_1".into()),
+        name: "pwd".into(),
+        result: "/tmp".into(),
+        duration: Duration::from_millis(12),
+        error: None,
+    };
+
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        json!({
+            "type": "tool_call_finished",
+            "id": "call_1",
+            "name": "pwd",
+            "result": "/tmp",
+            "duration_ms": 12,
+            "error": null,
+        })
+    );
+}
+
+fn quick_retries(max_retries: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_retries,
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+    }
+}
+
+#[tokio::test]
+async fn retries_while_the_server_is_unavailable() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::error(503, "server busy"))
+        .enqueue(CHAT, MockResponse::answer("Hello"));
+    let (handler, mut events) = events::channel();
+    let mut assistant = builder(&server)
+        .with_retry_policy(quick_retries(2))
+        .with_event_handler(handler)
+        .build();
+
+    assert_eq!(ask(&mut assistant, "Hi").await.unwrap(), "Hello");
+
+    assert_eq!(server.requests(CHAT).len(), 2);
+    assert_eq!(assistant.stats().requests, 1);
+    let mut retried = false;
+    while let Ok(event) = events.try_recv() {
+        if let AssistantEvent::Retrying { retry, error, .. } = event {
+            assert_eq!(retry, 1);
+            assert!(error.contains("server busy"), "{}", error);
+            retried = true;
+        }
+    }
+    assert!(retried);
+}
+
+#[tokio::test]
+async fn retries_while_the_model_is_loading() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::error(500, "timed out waiting for llama runner to start"),
+        )
+        .enqueue(CHAT, MockResponse::answer("Hello"));
+    let mut assistant = builder(&server).with_retry_policy(quick_retries(2)).build();
+
+    assert_eq!(ask(&mut assistant, "Hi").await.unwrap(), "Hello");
+
+    assert_eq!(server.requests(CHAT).len(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::error(503, "server busy"))
+        .enqueue(CHAT, MockResponse::error(502, "bad gateway"))
+        .enqueue(CHAT, MockResponse::answer("Too late"));
+    let mut assistant = builder(&server).with_retry_policy(quick_retries(1)).build();
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    assert!(error.is_transient());
+    assert!(matches!(
+        error,
+        AssistantError::BackendUnavailable(OllamaError::HttpError { ref message, .. }) if message == "bad gateway"
+    ));
+    assert_eq!(server.requests(CHAT).len(), 2);
+}
+
+#[tokio::test]
+async fn slow_servers_time_out() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        CHAT,
+        MockResponse::Delayed(
+            Duration::from_secs(5),
+            Box::new(MockResponse::answer("Too late")),
+        ),
+    );
+    let mut assistant = builder(&server)
+        .with_retry_policy(RetryPolicy::none())
+        .with_timeouts(Timeouts {
+            request: Duration::from_millis(50),
+            ..Timeouts::default()
+        })
+        .build();
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        AssistantError::BackendUnavailable(OllamaError::TimeoutError {
+            waiting_for: "response from the server",
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn stalled_streams_time_out() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::Stalled(vec![], Duration::from_secs(5)))
+        .enqueue(
+            CHAT,
+            MockResponse::Stalled(vec![chunk("Hel", false)], Duration::from_secs(5)),
+        );
+    let mut assistant = builder(&server)
+        .with_retry_policy(RetryPolicy::none())
+        .with_timeouts(Timeouts {
+            first_token: Duration::from_millis(100),
+            idle: Duration::from_millis(100),
+            ..Timeouts::default()
+        })
+        .build();
+
+    for expected in ["first token", "next token"] {
+        let error = ask(&mut assistant, "Hi").await.unwrap_err();
+        assert!(
+            matches!(
+                error,
+                AssistantError::BackendUnavailable(OllamaError::TimeoutError { waiting_for, .. })
+                    if waiting_for == expected
+            ),
+            "{:?}",
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn context_overflows_are_recognized() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        CHAT,
+        MockResponse::Json(
+            400,
+            json!({ "error": {
+                "message": "This model's maximum context length is 4096 tokens",
+                "code": "context_length_exceeded"
+            } }),
+        ),
+    );
+    let mut assistant = assistant(&server);
+
+    let error = ask(&mut assistant, "Hi").await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        AssistantError::ContextOverflow(ref message) if message.contains("4096 tokens")
+    ));
+    assert!(!error.is_transient());
+}
+
+#[tokio::test]
+async fn broken_tools_fail_the_question() {
+    let server = MockServer::start().await;
+    server.enqueue(CHAT, MockResponse::tool_call("ticket", json!({ "id": 0 })));
+    let mut assistant = builder(&server).with_tool(Tickets).build();
+
+    let error = ask(&mut assistant, "What's ticket 0?").await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        AssistantError::ToolFailure { ref tool, ref message }
+            if tool == "ticket" && message == "ticket server is down"
+    ));
+    assert_eq!(assistant.conversation().len(), 1);
+}
+
+#[test]
+fn retry_delays_double_up_to_the_limit() {
+    let policy = RetryPolicy {
+        max_retries: 5,
+        initial_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(3),
+    };
+
+    let delays: Vec<u128> = (1..=5)
+        .map(|retry| policy.delay(retry).as_millis())
+        .collect();
+    assert_eq!(delays, [500, 1000, 2000, 3000, 3000]);
+}
+
+type Notes = std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+/// Reads or writes notes, depending on `write`
+struct Notebook {
+    notes: Notes,
+    write: bool,
+}
+
+impl Tool for Notebook {
+    fn definition(&self) -> ToolDefinition {
+        let (name, parameters) = if self.write {
+            (
+                "add_note",
+                json!({
+                    "type": "object",
+                    "properties": { "text": { "type": "string" } },
+                    "required": ["text"]
+                }),
+            )
+        } else {
+            ("read_notes", json!({ "type": "object", "properties": {} }))
+        };
+        ToolDefinition::function(name, "Notes", Some(parameters))
+    }
+
+    fn is_read_only(&self) -> bool {
+        !self.write
+    }
+
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>> {
+        Box::pin(async move {
+            let mut notes = self.notes.lock().unwrap();
+            if self.write {
+                notes.push(arguments["text"].as_str().unwrap().to_string());
+            }
+            Ok(format!("Notes: {}", notes.join(", ")))
+        })
+    }
+}
+
+/// Needs approval before it runs
+struct Deploy;
+
+impl Tool for Deploy {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::function(
+            "deploy",
+            "Deploys the project",
+            Some(json!({
+                "type": "object",
+                "properties": { "target": { "type": "string" } },
+                "required": ["target"]
+            })),
+        )
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn needs_approval(&self) -> bool {
+        true
+    }
+
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>> {
+        Box::pin(async move { Ok(format!("Deployed to {}", arguments["target"])) })
+    }
+}
+
+fn notebook(server: &MockServer, notes: &Notes) -> Assistant {
+    builder(server)
+        .with_tool(Notebook {
+            notes: notes.clone(),
+            write: false,
+        })
+        .with_tool(Notebook {
+            notes: notes.clone(),
+            write: true,
+        })
+        .build()
+}
+
+#[tokio::test]
+async fn writes_invalidate_earlier_results() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::tool_call("read_notes", json!({})))
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("add_note", json!({ "text": "milk" })),
+        )
+        .enqueue(CHAT, MockResponse::tool_call("read_notes", json!({})))
+        .enqueue(CHAT, MockResponse::answer("Done"));
+    let notes = Notes::default();
+    let mut assistant = notebook(&server, &notes);
+
+    ask(&mut assistant, "Add milk").await.unwrap();
+
+    let requests = server.requests(CHAT);
+    assert_eq!(last_message(&requests[1])["content"], "Notes: ");
+    assert_eq!(last_message(&requests[3])["content"], "Notes: milk");
+}
+
+#[tokio::test]
+async fn repeated_writes_run_again() {
+    let server = MockServer::start().await;
+    for _ in 0..2 {
+        server.enqueue(
+            CHAT,
+            MockResponse::tool_call("add_note", json!({ "text": "milk" })),
+        );
+    }
+    server.enqueue(CHAT, MockResponse::answer("Done"));
+    let notes = Notes::default();
+    let mut assistant = notebook(&server, &notes);
+
+    ask(&mut assistant, "Add milk twice").await.unwrap();
+
+    assert_eq!(*notes.lock().unwrap(), ["milk", "milk"]);
+    let requests = server.requests(CHAT);
+    assert_eq!(last_message(&requests[2])["content"], "Notes: milk, milk");
+}
+
+#[tokio::test]
+async fn repeated_calls_ask_for_approval_again() {
+    let server = MockServer::start().await;
+    for _ in 0..2 {
+        server.enqueue(
+            CHAT,
+            MockResponse::tool_call("deploy", json!({ "target": "staging" })),
+        );
+    }
+    server.enqueue(CHAT, MockResponse::answer("Deployed"));
+    let asked = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let answers = asked.clone();
+    let mut assistant = builder(&server)
+        .with_tool(Deploy)
+        .with_approval_callback(Box::new(move |_| {
+            // Denied the first time, allowed after changing their mind
+            let approved = answers.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 0;
+            Box::pin(async move { approved })
+        }))
+        .build();
+
+    ask(&mut assistant, "Deploy").await.unwrap();
+
+    assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 2);
+    let requests = server.requests(CHAT);
+    let denied = last_message(&requests[1])["content"].as_str().unwrap();
+    assert!(denied.contains("did not allow"), "{}", denied);
+    assert_eq!(
+        last_message(&requests[2])["content"],
+        "Deployed to \"staging\""
+    );
+}
+
+#[tokio::test]
+async fn failed_calls_are_not_repeated_from_memory() {
+    let server = MockServer::start().await;
+    for _ in 0..2 {
+        server.enqueue(
+            CHAT,
+            MockResponse::tool_call("read_file", json!({ "path": "missing.txt" })),
+        );
+    }
+    server.enqueue(CHAT, MockResponse::answer("No such file"));
+    let mut assistant = assistant(&server);
+
+    ask(&mut assistant, "Read missing.txt").await.unwrap();
+
+    let requests = server.requests(CHAT);
+    let second = last_message(&requests[2])["content"].as_str().unwrap();
+    assert!(second.starts_with("ERROR:"), "{}", second);
+    assert!(!second.contains("NOTE: You already called"));
+    assert_eq!(assistant.stats().tool_errors, 2);
+}
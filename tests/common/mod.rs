@@ -0,0 +1,274 @@
+//! A mock HTTP server for the integration tests. Not every test uses all
+//! of it.
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// A scripted answer for one request
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+    /// A JSON body with the given status
+    Json(u16, Value),
+    /// Newline delimited JSON, written one line at a time like Ollama does
+    Chunks(Vec<Value>),
+    /// Any body, e.g. malformed JSON or server-sent events
+    Raw {
+        status: u16,
+        content_type: &'static str,
+        body: String,
+    },
+    /// Waits before answering, for testing cancellation
+    Delayed(Duration, Box<MockResponse>),
+    /// Sends the chunks, then keeps the connection open without sending
+    /// anything for a while, for testing timeouts
+    Stalled(Vec<Value>, Duration),
+}
+
+impl MockResponse {
+    /// A complete /api/chat response
+    pub fn chat(message: Value) -> Self {
+        MockResponse::Json(
+            200,
+            json!({
+                "model": "mock",
+                "created_at": "2025-01-01T00:00:00Z",
+                "message": message,
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 10,
+                "eval_count": 5,
+            }),
+        )
+    }
+
+    pub fn answer(content: &str) -> Self {
+        Self::chat(json!({ "role": "assistant", "content": content }))
+    }
+
+    pub fn tool_call(name: &str, arguments: Value) -> Self {
+        Self::tool_calls(&[(name, arguments)])
+    }
+
+    pub fn tool_calls(calls: &[(&str, Value)]) -> Self {
+        let calls: Vec<Value> = calls
+            .iter()
+            .map(
+                |(name, arguments)| json!({ "function": { "name": name, "arguments": arguments } }),
+            )
+            .collect();
+        Self::chat(json!({ "role": "assistant", "content": "", "tool_calls": calls }))
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        MockResponse::Json(status, json!({ "error": message }))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    responses: HashMap<String, VecDeque<MockResponse>>,
+    requests: Vec<(String, Value)>,
+    /// Names and /api/show responses of the installed models
+    models: Vec<(String, Value)>,
+}
+
+/// A minimal HTTP server standing in for Ollama or an OpenAI compatible
+/// server. Responses are queued per path and served in order. Requests are
+/// recorded so tests can check what was sent. Without queued responses,
+/// /api/tags and /api/show answer for the installed models.
+pub struct MockServer {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle(stream, server_state.clone()));
+            }
+        });
+
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn enqueue(&self, path: &str, response: MockResponse) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .entry(path.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Adds a model to /api/tags and /api/show. Models without
+    /// capabilities look like they come from an older Ollama version.
+    pub fn install(&self, name: &str, capabilities: &[&str]) -> &Self {
+        let show = json!({
+            "details": { "parameter_size": "8.0B", "quantization_level": "Q4_K_M" },
+            "model_info": { "llama.context_length": 131072 },
+            "capabilities": capabilities,
+        });
+        self.state
+            .lock()
+            .unwrap()
+            .models
+            .push((name.to_string(), show));
+        self
+    }
+
+    /// The JSON bodies of all requests to a path, in order
+    pub fn requests(&self, path: &str) -> Vec<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|(p, _)| p == path)
+            .map(|(_, body)| body.clone())
+            .collect()
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let Some((path, body)) = read_request(&mut stream).await else {
+        return;
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push((path.clone(), body.clone()));
+        state
+            .responses
+            .get_mut(&path)
+            .and_then(VecDeque::pop_front)
+            .or_else(|| installed_models(&state, &path, &body))
+    };
+    let response = response
+        .unwrap_or_else(|| MockResponse::error(404, &format!("no mock response for {}", path)));
+
+    write_response(&mut stream, response).await;
+}
+
+fn installed_models(state: &State, path: &str, body: &Value) -> Option<MockResponse> {
+    match path {
+        "/api/tags" => {
+            let models: Vec<Value> = state
+                .models
+                .iter()
+                .map(|(name, _)| json!({ "name": name, "model": name }))
+                .collect();
+            Some(MockResponse::Json(200, json!({ "models": models })))
+        }
+        "/api/show" => {
+            let name = body["model"].as_str().unwrap_or_default();
+            Some(
+                match state.models.iter().find(|(installed, _)| installed == name) {
+                    Some((_, show)) => MockResponse::Json(200, show.clone()),
+                    None => MockResponse::error(404, &format!("model '{}' not found", name)),
+                },
+            )
+        }
+        _ => None,
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<(String, Value)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let path = head.split_whitespace().nth(1)?.to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buffer.len() < header_end + content_length {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = serde_json::from_slice(&buffer[header_end..]).unwrap_or(Value::Null);
+    Some((path, body))
+}
+
+async fn write_response(stream: &mut TcpStream, response: MockResponse) {
+    match response {
+        MockResponse::Json(status, body) => {
+            write_complete(stream, status, "application/json", &body.to_string()).await
+        }
+        MockResponse::Raw {
+            status,
+            content_type,
+            body,
+        } => write_complete(stream, status, content_type, &body).await,
+        MockResponse::Chunks(chunks) => write_chunks(stream, chunks).await,
+        MockResponse::Stalled(chunks, stall) => {
+            write_chunks(stream, chunks).await;
+            tokio::time::sleep(stall).await;
+        }
+        MockResponse::Delayed(delay, response) => {
+            tokio::time::sleep(delay).await;
+            Box::pin(write_response(stream, *response)).await
+        }
+    }
+    let _ = stream.shutdown().await;
+}
+
+async fn write_chunks(stream: &mut TcpStream, chunks: Vec<Value>) {
+    // No content length, the body ends when the connection closes
+    let head = "HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\nconnection: close\r\n\r\n";
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.flush().await;
+    for chunk in chunks {
+        let _ = stream.write_all(format!("{}\n", chunk).as_bytes()).await;
+        let _ = stream.flush().await;
+    }
+}
+
+async fn write_complete(stream: &mut TcpStream, status: u16, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {} Mock\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use serde_json::json;
+
+mod common;
+
+use common::{MockResponse, MockServer};
+use ollama_code::{
+    backend::{RetryPolicy, Timeouts},
+    config::ModelOptions,
+    eval::{self, Check, EvalSettings, Report, Task, TaskResult},
+    ollama::OllamaClient,
+};
+
+const CHAT: &str = "/api/chat";
+
+fn suite_dir() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/evals"))
+}
+
+fn task(name: &str) -> Task {
+    eval::load_suite(suite_dir())
+        .unwrap()
+        .into_iter()
+        .find(|task| task.name == name)
+        .unwrap()
+}
+
+fn settings() -> EvalSettings {
+    EvalSettings {
+        options: ModelOptions {
+            temperature: 0.5,
+            top_p: None,
+            top_k: None,
+            seed: Some(42),
+            num_ctx: 4096,
+            num_predict: None,
+            repeat_penalty: None,
+            stop: Vec::new(),
+            keep_alive: None,
+        },
+        retry: RetryPolicy::none(),
+        timeouts: Timeouts::default(),
+        max_turns: 10,
+        system_prompt: None,
+    }
+}
+
+async fn run(server: &MockServer, task: &Task) -> eval::TaskResult {
+    let backend = Box::new(OllamaClient::new(server.url()));
+    eval::run_task(task, "mock", backend, true, &settings()).await
+}
+
+#[test]
+fn loads_the_bundled_suite() {
+    let tasks = eval::load_suite(suite_dir()).unwrap();
+
+    let names: Vec<&str> = tasks.iter().map(|task| task.name.as_str()).collect();
+    assert_eq!(names, ["list-operators", "operators", "package-version"]);
+    assert!(
+        tasks
+            .iter()
+            .all(|task| task.fixture.join("Cargo.toml").is_file())
+    );
+    assert_eq!(task("operators").max_turns, Some(6));
+}
+
+#[tokio::test]
+async fn passes_when_the_checks_pass() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("read_file", json!({ "path": "Cargo.toml" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("The version is 0.3.1."));
+
+    let result = run(&server, &task("package-version")).await;
+
+    assert!(result.passed, "{:?}", result.failures);
+    assert_eq!(result.stats.requests, 2);
+    assert_eq!(result.stats.tool_calls, 1);
+    assert_eq!(result.stats.tool_errors, 0);
+    assert_eq!(result.stats.prompt_tokens, 20);
+    assert_eq!(result.stats.completion_tokens, 10);
+
+    // Relative paths resolve against the copy of the fixture
+    let requests = server.requests(CHAT);
+    let messages = requests[1]["messages"].as_array().unwrap();
+    let tool_result = messages.last().unwrap()["content"].as_str().unwrap();
+    assert!(
+        tool_result.contains("version = \"0.3.1\""),
+        "{}",
+        tool_result
+    );
+}
+
+#[tokio::test]
+async fn reports_failed_checks_and_tool_errors() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_calls(&[
+                ("read_file", json!({ "path": "src/missing.rs" })),
+                ("nope", json!({})),
+            ]),
+        )
+        .enqueue(CHAT, MockResponse::answer("It supports +, - and %."));
+
+    let result = run(&server, &task("list-operators")).await;
+
+    assert!(!result.passed);
+    assert_eq!(
+        result.failures,
+        ["answer doesn't contain '^'", "answer contains '%'"]
+    );
+    assert_eq!(result.stats.tool_calls, 2);
+    assert_eq!(result.stats.tool_errors, 2);
+}
+
+#[tokio::test]
+async fn model_errors_fail_the_task() {
+    let server = MockServer::start().await;
+    server.enqueue(CHAT, MockResponse::error(500, "out of memory"));
+
+    let result = run(&server, &task("package-version")).await;
+
+    assert!(!result.passed);
+    assert_eq!(result.failures.len(), 1);
+    assert!(
+        result.failures[0].contains("out of memory"),
+        "{:?}",
+        result.failures
+    );
+}
+
+#[tokio::test]
+async fn summarizes_per_model() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::answer("Version 0.3.1"))
+        .enqueue(CHAT, MockResponse::answer("I don't know"));
+    let task = task("package-version");
+
+    let passed = run(&server, &task).await;
+    let mut failed = run(&server, &task).await;
+    failed.model = "other".into();
+    let report = Report::new(vec![passed, failed]);
+
+    let models: Vec<&str> = report.summaries.iter().map(|s| s.model.as_str()).collect();
+    assert_eq!(models, ["mock", "other"]);
+    assert_eq!(report.summaries[0].success_rate, 1.0);
+    assert_eq!(report.summaries[1].success_rate, 0.0);
+    assert_eq!(report.summaries[0].prompt_tokens, 10);
+
+    let table = report.table();
+    assert!(table.starts_with("MODEL"), "{}", table);
+    assert!(table.contains("1/1 (100%)"), "{}", table);
+    assert!(table.contains("0/1 (0%)"), "{}", table);
+}
+
+#[test]
+fn models_that_cant_run_fail_every_task() {
+    let tasks = eval::load_suite(suite_dir()).unwrap();
+
+    let results = tasks
+        .iter()
+        .map(|task| TaskResult::failed(task, "missing", "model 'missing' not found".into()))
+        .collect();
+    let report = Report::new(results);
+
+    assert_eq!(report.summaries[0].tasks, tasks.len());
+    assert_eq!(report.summaries[0].passed, 0);
+    assert!(report.table().contains("0/3 (0%)"), "{}", report.table());
+}
+
+#[test]
+fn checks_stay_inside_the_workspace() {
+    let workspace = task("package-version").fixture;
+    let check = |path: &str| Check::FileContains {
+        path: path.into(),
+        text: "[package]".into(),
+    };
+
+    assert_eq!(check("./Cargo.toml").verify("", &workspace), Ok(()));
+    assert_eq!(
+        check("../calculator/Cargo.toml").verify("", &workspace),
+        Err("../calculator/Cargo.toml is outside of the workspace, use a relative path".into())
+    );
+    assert_eq!(
+        check("/etc/passwd").verify("", &workspace),
+        Err("/etc/passwd is outside of the workspace, use a relative path".into())
+    );
+}
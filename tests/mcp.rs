@@ -0,0 +1,494 @@
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio_util::sync::CancellationToken;
+
+mod common;
+
+use common::{MockResponse, MockServer};
+use ollama_code::{
+    assistant::Assistant,
+    mcp::{self, McpClient, McpError, McpServer, McpServerConfig, Permission},
+    ollama::OllamaClient,
+    tools::{Tool, ToolError, ToolSet, Toolchain},
+};
+
+const CHAT: &str = "/api/chat";
+const FIXTURE: &str = "evals/fixtures/calculator";
+
+type Requests = Arc<Mutex<Vec<Value>>>;
+
+/// An MCP server on the other end of an in-memory pipe, with a small ticket
+/// system. Lists its tools in two pages. Hangs up when `crash` is called,
+/// never answers a search for "hang".
+async fn connect() -> (McpClient, Requests) {
+    let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client_side);
+    let (server_read, mut server_write) = tokio::io::split(server_side);
+    let requests = Requests::default();
+
+    let received = requests.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(server_read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let message: Value = serde_json::from_str(&line).unwrap();
+            received.lock().unwrap().push(message.clone());
+            let Some(id) = message.get("id") else {
+                continue;
+            };
+            let params = &message["params"];
+            let result = match message["method"].as_str().unwrap() {
+                "initialize" => Ok(json!({
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "tickets", "version": "1.0" },
+                })),
+                "tools/list" if params["cursor"].is_null() => Ok(json!({
+                    "tools": [
+                        tool("search", "query", true),
+                        tool("delete", "id", false),
+                    ],
+                    "nextCursor": "2",
+                })),
+                "tools/list" => Ok(json!({
+                    "tools": [tool("create", "title", false), tool("crash", "why", false)],
+                })),
+                "tools/call" => match params["name"].as_str().unwrap() {
+                    // Never answers
+                    "search" if params["arguments"]["query"] == "hang" => continue,
+                    "search" => Ok(text(&format!(
+                        "Found ticket 42 for {}",
+                        params["arguments"]["query"]
+                    ))),
+                    "delete" => Ok(json!({
+                        "content": [{ "type": "text", "text": "Ticket is locked" }],
+                        "isError": true,
+                    })),
+                    "create" if params["arguments"]["title"].is_string() => {
+                        Ok(text("Created ticket 43"))
+                    }
+                    "create" => Err("Missing title"),
+                    _ => break,
+                },
+                _ => Err("Method not found"),
+            };
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(message) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32602, "message": message },
+                }),
+            };
+            let line = format!("{}\n", response);
+            server_write.write_all(line.as_bytes()).await.unwrap();
+        }
+    });
+
+    let client = McpClient::connect(client_read, client_write).await.unwrap();
+    (client, requests)
+}
+
+fn tool(name: &str, argument: &str, read_only: bool) -> Value {
+    json!({
+        "name": name,
+        "description": format!("The {} tool", name),
+        "inputSchema": {
+            "type": "object",
+            "properties": { argument: { "type": "string" } },
+            "required": [argument],
+        },
+        "annotations": { "readOnlyHint": read_only },
+    })
+}
+
+fn text(text: &str) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }] })
+}
+
+fn config(permission: Permission, tools: &[(&str, Permission)]) -> McpServerConfig {
+    McpServerConfig {
+        permission,
+        tools: tools
+            .iter()
+            .map(|(name, permission)| (name.to_string(), *permission))
+            .collect(),
+        ..McpServerConfig::new("tickets")
+    }
+}
+
+async fn server(config: &McpServerConfig) -> (McpServer, Requests) {
+    let (client, requests) = connect().await;
+    let server = McpServer::new("tickets", client, config).await.unwrap();
+    (server, requests)
+}
+
+fn find<'a>(server: &'a McpServer, name: &str) -> &'a dyn Tool {
+    server
+        .tools()
+        .iter()
+        .find(|tool| tool.definition().function.name == name)
+        .unwrap()
+}
+
+fn calls(requests: &Requests) -> Vec<Value> {
+    requests
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|request| request["method"] == "tools/call")
+        .map(|request| request["params"].clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn completes_the_handshake() {
+    let (client, requests) = connect().await;
+
+    assert_eq!(client.server_name(), "tickets");
+    // The server answers in order, so it has seen the notification by now
+    assert_eq!(client.list_tools().await.unwrap().len(), 4);
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests[0]["method"], "initialize");
+    assert_eq!(requests[0]["params"]["clientInfo"]["name"], "ollama_code");
+    assert_eq!(requests[1]["method"], "notifications/initialized");
+    assert!(requests[1].get("id").is_none());
+}
+
+#[tokio::test]
+async fn lists_tools_and_applies_permissions() {
+    let config = config(
+        Permission::Allow,
+        &[("delete", Permission::Deny), ("create", Permission::Ask)],
+    );
+    let (server, _) = server(&config).await;
+
+    let names: Vec<String> = server
+        .tools()
+        .iter()
+        .map(|tool| tool.definition().function.name)
+        .collect();
+    assert_eq!(names, ["search", "create", "crash"]);
+
+    let search = find(&server, "search");
+    assert!(search.is_read_only());
+    assert!(!search.needs_approval());
+    assert_eq!(search.definition().function.description, "The search tool");
+    assert_eq!(
+        search.definition().function.parameters.unwrap()["required"],
+        json!(["query"])
+    );
+    assert!(find(&server, "create").needs_approval());
+    assert!(!find(&server, "crash").is_read_only());
+}
+
+#[tokio::test]
+async fn calls_go_to_the_server() {
+    let (server, requests) = server(&config(Permission::Allow, &[])).await;
+
+    let result = find(&server, "search")
+        .call(&json!({ "query": "build" }))
+        .await;
+    assert_eq!(result.unwrap(), "Found ticket 42 for \"build\"");
+
+    let result = find(&server, "delete").call(&json!({ "id": "42" })).await;
+    assert_eq!(
+        result.unwrap_err(),
+        ToolError::Failed("Ticket is locked".into())
+    );
+
+    let result = find(&server, "create").call(&json!({})).await;
+    assert_eq!(
+        result.unwrap_err(),
+        ToolError::Failed("Missing title".into())
+    );
+
+    assert_eq!(
+        calls(&requests)[0],
+        json!({ "name": "search", "arguments": { "query": "build" } })
+    );
+}
+
+#[tokio::test]
+async fn tools_break_when_the_server_goes_away() {
+    let (server, _) = server(&config(Permission::Allow, &[])).await;
+
+    let result = find(&server, "crash")
+        .call(&json!({ "why": "testing" }))
+        .await;
+    assert!(matches!(result, Err(ToolError::Broken(_))), "{:?}", result);
+
+    let result = find(&server, "search")
+        .call(&json!({ "query": "build" }))
+        .await;
+    assert!(
+        matches!(result, Err(ToolError::Broken(ref message)) if message.contains("closed")),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn slow_calls_time_out_and_are_cancelled() {
+    let config = McpServerConfig {
+        call_timeout: Some(1),
+        ..config(Permission::Allow, &[])
+    };
+    let (server, requests) = server(&config).await;
+
+    let result = find(&server, "search")
+        .call(&json!({ "query": "hang" }))
+        .await;
+    assert_eq!(
+        result.unwrap_err(),
+        ToolError::Failed("MCP server 'tickets' didn't answer within 1s".into())
+    );
+
+    // The server is still usable
+    let result = find(&server, "search")
+        .call(&json!({ "query": "build" }))
+        .await;
+    assert_eq!(result.unwrap(), "Found ticket 42 for \"build\"");
+
+    let cancelled = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            let cancelled = requests
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|request| request["method"] == "notifications/cancelled")
+                .cloned();
+            match cancelled {
+                Some(cancelled) => return cancelled,
+                None => tokio::task::yield_now().await,
+            }
+        }
+    })
+    .await
+    .expect("the server wasn't told about the cancelled call");
+    let requests = requests.lock().unwrap();
+    let hung = requests
+        .iter()
+        .find(|request| request["params"]["arguments"]["query"] == "hang")
+        .unwrap();
+    assert_eq!(cancelled["params"]["requestId"], hung["id"]);
+}
+
+#[tokio::test]
+async fn missing_commands_fail_to_start() {
+    let result = McpClient::start(&McpServerConfig::new("ollama-code-no-such-server")).await;
+
+    assert!(matches!(result, Err(McpError::Io(_))), "{:?}", result);
+}
+
+fn assistant(model: &MockServer, server: &McpServer) -> ollama_code::AssistantBuilder {
+    let mut builder = Assistant::builder("mock")
+        .with_system_prompt("You are a test")
+        .with_backend(Box::new(OllamaClient::new(model.url())));
+    for tool in server.tools() {
+        builder = builder.with_tool(tool.clone());
+    }
+    builder
+}
+
+#[tokio::test]
+async fn mcp_tools_are_offered_to_the_model() {
+    let model = MockServer::start().await;
+    model
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("search", json!({ "query": "flaky" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("Ticket 42"));
+    let (server, requests) = server(&config(Permission::Allow, &[])).await;
+    let mut assistant = assistant(&model, &server).build();
+
+    let answer = assistant
+        .ask("Is there a ticket?", &CancellationToken::new())
+        .await
+        .unwrap();
+
+    assert_eq!(answer, "Ticket 42");
+    let chat = model.requests(CHAT);
+    let offered: Vec<&Value> = chat[0]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| &tool["function"]["name"])
+        .collect();
+    assert!(offered.contains(&&json!("search")));
+    assert!(offered.contains(&&json!("read_file")));
+    assert_eq!(calls(&requests).len(), 1);
+    let last = chat[1]["messages"].as_array().unwrap().last().unwrap();
+    assert_eq!(last["content"], "Found ticket 42 for \"flaky\"");
+}
+
+#[tokio::test]
+async fn calls_that_need_approval_are_denied_without_a_callback() {
+    let model = MockServer::start().await;
+    model
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("create", json!({ "title": "Flaky test" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("I couldn't"));
+    let (server, requests) = server(&config(Permission::Ask, &[])).await;
+    let mut assistant = assistant(&model, &server).build();
+
+    assistant
+        .ask("Create a ticket", &CancellationToken::new())
+        .await
+        .unwrap();
+
+    assert!(calls(&requests).is_empty());
+    assert_eq!(assistant.stats().tool_errors, 1);
+    let chat = model.requests(CHAT);
+    let last = chat[1]["messages"].as_array().unwrap().last().unwrap();
+    assert!(
+        last["content"]
+            .as_str()
+            .unwrap()
+            .contains("did not allow this call to create"),
+        "{}",
+        last
+    );
+}
+
+#[tokio::test]
+async fn the_approval_callback_decides() {
+    let model = MockServer::start().await;
+    model
+        .enqueue(
+            CHAT,
+            MockResponse::tool_calls(&[
+                ("create", json!({ "title": "Flaky test" })),
+                ("create", json!({ "title": "Slow build" })),
+            ]),
+        )
+        .enqueue(CHAT, MockResponse::answer("Created one"));
+    let (server, requests) = server(&config(Permission::Ask, &[])).await;
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let mut assistant = assistant(&model, &server)
+        .with_approval_callback(Box::new({
+            let asked = asked.clone();
+            move |call| {
+                let title = call.function.arguments["title"].clone();
+                asked.lock().unwrap().push(title.clone());
+                Box::pin(async move { title == "Flaky test" })
+            }
+        }))
+        .build();
+
+    assistant
+        .ask("Create tickets", &CancellationToken::new())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        *asked.lock().unwrap(),
+        [json!("Flaky test"), json!("Slow build")]
+    );
+    let calls = calls(&requests);
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0]["arguments"]["title"], "Flaky test");
+}
+
+/// A client talking to `mcp::serve` with the built-in tools
+async fn serve_toolchain() -> McpClient {
+    let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client_side);
+    let (server_read, server_write) = tokio::io::split(server_side);
+    tokio::spawn(async move {
+        let mut tools = ToolSet::default();
+        for tool in Toolchain::new(FIXTURE).tools() {
+            tools.add(tool);
+        }
+        mcp::serve(&tools, server_read, server_write).await.unwrap();
+    });
+    McpClient::connect(client_read, client_write).await.unwrap()
+}
+
+#[tokio::test]
+async fn serves_the_built_in_tools() {
+    let client = serve_toolchain().await;
+
+    assert_eq!(client.server_name(), "ollama_code");
+    let tools = client.list_tools().await.unwrap();
+    let names: Vec<&str> = tools.iter().map(|tool| tool.name.as_str()).collect();
+    assert_eq!(names, ["list_directory", "read_file", "pwd", "grep"]);
+    assert!(tools.iter().all(|tool| tool.annotations.read_only_hint));
+    let definitions = Toolchain::new(FIXTURE).definitions();
+    assert_eq!(tools[1].input_schema, definitions[1].function.parameters);
+
+    let arguments = json!({ "path": "Cargo.toml" });
+    let result = client.call_tool("read_file", &arguments).await.unwrap();
+    assert!(!result.is_error);
+    assert_eq!(
+        result.text(),
+        Toolchain::new(FIXTURE)
+            .call("read_file", &arguments)
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn served_calls_fail_like_the_assistants() {
+    let client = serve_toolchain().await;
+
+    let arguments = json!({ "path": "src/missing.rs" });
+    let result = client.call_tool("read_file", &arguments).await.unwrap();
+    assert!(result.is_error);
+    assert_eq!(
+        result.text(),
+        Toolchain::new(FIXTURE)
+            .call("read_file", &arguments)
+            .unwrap_err()
+    );
+
+    let result = client.call_tool("read_file", &json!({})).await.unwrap();
+    assert!(result.is_error);
+    assert!(result.text().contains("missing required property 'path'"));
+
+    let result = client
+        .call_tool("read_file", &json!({ "path": "../../../Cargo.toml" }))
+        .await
+        .unwrap();
+    assert!(result.is_error);
+    assert!(
+        result.text().contains("Outside of the workspace"),
+        "{}",
+        result.text()
+    );
+
+    let result = client.call_tool("write_file", &json!({})).await;
+    assert!(
+        matches!(result, Err(McpError::Server { code: -32602, ref message }) if message.contains("write_file")),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn mcp_serve_speaks_over_stdio() {
+    let config = McpServerConfig {
+        args: vec!["--path".into(), FIXTURE.into(), "mcp-serve".into()],
+        ..McpServerConfig::new(env!("CARGO_BIN_EXE_ollama_code"))
+    };
+    let client = McpClient::start(&config).await.unwrap();
+
+    let result = client
+        .call_tool(
+            "grep",
+            &json!({ "path": "src/ops.rs", "search_pattern": "powf" }),
+        )
+        .await
+        .unwrap();
+
+    assert!(!result.is_error);
+    assert!(
+        result.text().starts_with("Found 1 matches"),
+        "{}",
+        result.text()
+    );
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::MockServer;
+use ollama_code::{
+    models::{self, ModelError},
+    ollama::OllamaClient,
+};
+
+#[tokio::test]
+async fn finds_installed_models() {
+    let server = MockServer::start().await;
+    server
+        .install("llama3.1:latest", &["completion", "tools"])
+        .install("qwen2.5-coder:7b", &["completion", "tools"]);
+    let client = OllamaClient::new(server.url());
+
+    let info = models::check(&client, "llama3.1").await.unwrap();
+
+    assert_eq!(info.name, "llama3.1:latest");
+    assert_eq!(info.parameter_size, "8.0B");
+    assert_eq!(info.context_length, Some(131072));
+    assert!(info.supports_tools());
+    assert_eq!(server.requests("/api/show")[0]["model"], "llama3.1:latest");
+}
+
+#[tokio::test]
+async fn missing_models_fail_with_suggestions() {
+    let server = MockServer::start().await;
+    server
+        .install("qwen2.5-coder:7b", &["completion", "tools"])
+        .install("gemma2:2b", &["completion"])
+        .install("llama3.1:8b", &["completion", "tools"]);
+    let client = OllamaClient::new(server.url());
+
+    let error = models::check(&client, "mistral").await.unwrap_err();
+
+    match &error {
+        ModelError::NotInstalled { model, suggestions } => {
+            assert_eq!(model, "mistral");
+            assert_eq!(suggestions, &["llama3.1:8b", "qwen2.5-coder:7b"]);
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(
+        error.to_string(),
+        "Model 'mistral' is not installed. Pull it with `ollama pull mistral`\n\
+         Installed models with tool support: llama3.1:8b, qwen2.5-coder:7b"
+    );
+}
+
+#[tokio::test]
+async fn reports_an_unreachable_server() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        "/api/tags",
+        common::MockResponse::error(500, "something broke"),
+    );
+    let client = OllamaClient::new(server.url());
+
+    let error = models::check(&client, "llama3.1").await.unwrap_err();
+
+    assert!(matches!(error, ModelError::Ollama(_)), "{:?}", error);
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::{MockResponse, MockServer};
+use futures::StreamExt;
+use ollama_code::ollama::{ChatMessage, ChatRequest, OllamaClient, OllamaError, PullProgress};
+use serde_json::json;
+
+#[tokio::test]
+async fn chats_without_streaming() {
+    let server = MockServer::start().await;
+    server.enqueue("/api/chat", MockResponse::answer("Hello!"));
+    let client = OllamaClient::new(server.url());
+    let mut request = ChatRequest::new("llama3.1", vec![ChatMessage::user("Hi")]);
+    request.stream = true;
+
+    let response = client.chat(&request).await.unwrap();
+
+    assert_eq!(response.message.content, "Hello!");
+    assert!(response.done);
+    let sent = &server.requests("/api/chat")[0];
+    assert_eq!(sent["model"], "llama3.1");
+    assert_eq!(sent["stream"], false);
+    assert_eq!(
+        sent["messages"],
+        json!([{ "role": "user", "content": "Hi" }])
+    );
+}
+
+#[tokio::test]
+async fn reports_error_statuses() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            "/api/chat",
+            MockResponse::error(404, "model 'missing' not found"),
+        )
+        .enqueue("/api/chat", MockResponse::error(500, "out of memory"));
+    let client = OllamaClient::new(server.url());
+    let request = ChatRequest::new("missing", vec![ChatMessage::user("Hi")]);
+
+    let error = client.chat(&request).await.unwrap_err();
+    assert!(
+        matches!(&error, OllamaError::ModelNotFoundError(model) if model == "missing"),
+        "{:?}",
+        error
+    );
+
+    let error = client.chat(&request).await.unwrap_err();
+    match error {
+        OllamaError::HttpError { status, message } => {
+            assert_eq!(status, 500);
+            assert_eq!(message, "out of memory");
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn lists_and_shows_models() {
+    let server = MockServer::start().await;
+    server.install("llama3.1:8b", &["completion", "tools"]);
+    let client = OllamaClient::new(server.url());
+
+    let tags = client.tags().await.unwrap();
+    assert_eq!(tags.models.len(), 1);
+    assert_eq!(tags.models[0].name, "llama3.1:8b");
+
+    let show = client.show("llama3.1:8b").await.unwrap();
+    assert_eq!(show.details.parameter_size, "8.0B");
+    assert_eq!(show.details.quantization_level, "Q4_K_M");
+    assert_eq!(show.model_info["llama.context_length"], 131072);
+    assert_eq!(show.capabilities, ["completion", "tools"]);
+    assert_eq!(
+        server.requests("/api/show")[0],
+        json!({ "model": "llama3.1:8b" })
+    );
+
+    let error = client.show("mistral").await.unwrap_err();
+    assert!(
+        matches!(&error, OllamaError::ModelNotFoundError(model) if model == "mistral"),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn reports_malformed_responses() {
+    let server = MockServer::start().await;
+    server.enqueue("/api/tags", MockResponse::Json(200, json!({ "tags": [] })));
+    let client = OllamaClient::new(server.url());
+
+    let error = client.tags().await.unwrap_err();
+
+    match error {
+        OllamaError::MalformedResponseError(message) => {
+            assert!(message.contains("missing field `models`"), "{}", message);
+            assert!(message.ends_with(r#"{"tags":[]}"#), "{}", message);
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn streams_pull_progress() {
+    let server = MockServer::start().await;
+    server.enqueue(
+        "/api/pull",
+        MockResponse::Chunks(vec![
+            json!({ "status": "pulling manifest" }),
+            json!({ "status": "pulling sha256:8eeb", "digest": "sha256:8eeb", "total": 100, "completed": 40 }),
+            json!({ "status": "success" }),
+        ]),
+    );
+    let client = OllamaClient::new(server.url());
+
+    let progress: Vec<PullProgress> = client
+        .pull("llama3.1")
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    assert_eq!(progress.len(), 3);
+    assert_eq!(progress[1].total, Some(100));
+    assert_eq!(progress[1].completed, Some(40));
+    assert_eq!(progress[2].status, "success");
+    assert_eq!(
+        server.requests("/api/pull")[0],
+        json!({ "model": "llama3.1", "stream": true })
+    );
+}
+
+#[tokio::test]
+async fn pull_reports_errors() {
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            "/api/pull",
+            MockResponse::error(500, "registry unreachable"),
+        )
+        .enqueue(
+            "/api/pull",
+            MockResponse::Chunks(vec![
+                json!({ "status": "pulling manifest" }),
+                json!({ "error": "pull model manifest: file does not exist" }),
+            ]),
+        );
+    let client = OllamaClient::new(server.url());
+
+    let error = client.pull("llama3.1").await.err().unwrap();
+    assert!(
+        matches!(error, OllamaError::HttpError { .. }),
+        "{:?}",
+        error
+    );
+
+    let progress: Vec<_> = client.pull("nope").await.unwrap().collect().await;
+    assert_eq!(progress[0].as_ref().unwrap().status, "pulling manifest");
+    assert!(
+        matches!(&progress[1], Err(OllamaError::StreamedError(message)) if message == "pull model manifest: file does not exist"),
+        "{:?}",
+        progress[1]
+    );
+}
@@ -0,0 +1,195 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+mod common;
+
+use common::{MockResponse, MockServer};
+use ollama_code::{
+    assistant::{Assistant, AssistantBuilder, AssistantError},
+    backend::{ChatBackend, RetryPolicy},
+    config::ModelOptions,
+    events::{self, AssistantEvent},
+    ollama::{OllamaClient, OllamaError},
+    recording::{Recorder, Replayer},
+    tools::Toolchain,
+};
+
+const CHAT: &str = "/api/chat";
+
+fn recording_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ollama_code-{}-{}.jsonl", name, std::process::id()))
+}
+
+fn assistant(backend: Box<dyn ChatBackend>) -> Assistant {
+    builder(backend).build()
+}
+
+fn builder(backend: Box<dyn ChatBackend>) -> AssistantBuilder {
+    let options = ModelOptions {
+        temperature: 0.5,
+        top_p: None,
+        top_k: None,
+        seed: None,
+        num_ctx: 4096,
+        num_predict: None,
+        repeat_penalty: None,
+        stop: Vec::new(),
+        keep_alive: None,
+    };
+    Assistant::builder("mock")
+        .with_system_prompt("You are a test")
+        .with_options(options)
+        .with_backend(backend)
+        .with_retry_policy(RetryPolicy {
+            max_retries: 2,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+        })
+}
+
+#[tokio::test]
+async fn replays_a_recorded_session() {
+    let path = recording_path("replay");
+    let server = MockServer::start().await;
+    server
+        .enqueue(CHAT, MockResponse::error(503, "server busy"))
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("read_file", json!({ "path": "Cargo.toml" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("It's ollama_code"))
+        .enqueue(CHAT, MockResponse::error(400, "invalid options"));
+
+    let recorder = Recorder::new(Box::new(OllamaClient::new(server.url())), &path).unwrap();
+    let mut recorded = assistant(Box::new(recorder));
+    let cancel = CancellationToken::new();
+    let answer = recorded.ask("What's this?", &cancel).await.unwrap();
+    assert!(recorded.ask("And now?", &cancel).await.is_err());
+
+    // No server from here on
+    let replayer = Replayer::load(&path).unwrap();
+    assert!(replayer.native_tools());
+    let (handler, mut events) = events::channel();
+    let mut replayed = builder(Box::new(replayer))
+        .with_event_handler(handler)
+        .build();
+
+    // Retries where the recorded session did
+    assert_eq!(replayed.ask("What's this?", &cancel).await.unwrap(), answer);
+    assert_eq!(replayed.conversation(), recorded.conversation());
+    let mut retries = 0;
+    while let Ok(event) = events.try_recv() {
+        if let AssistantEvent::Retrying { error, .. } = event {
+            assert!(error.contains("server busy"), "{}", error);
+            retries += 1;
+        }
+    }
+    assert_eq!(retries, 1);
+    match replayed.ask("And now?", &cancel).await.unwrap_err() {
+        AssistantError::RequestFailed(OllamaError::ReplayError(error)) => {
+            assert!(error.contains("invalid options"))
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(matches!(
+        replayed.ask("Anything else?", &cancel).await.unwrap_err(),
+        AssistantError::RequestFailed(OllamaError::ReplayError(_))
+    ));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn reports_broken_recordings() {
+    let path = recording_path("broken");
+    std::fs::write(&path, "{\"request\": 1}\n").unwrap();
+
+    let error = Replayer::load(&path).unwrap_err();
+
+    assert!(error.to_string().contains(":1:"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn replays_fail_when_the_run_diverges() {
+    let path = recording_path("diverge");
+    let server = MockServer::start().await;
+    server
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("read_file", json!({ "path": "Cargo.toml" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("It's ollama_code"));
+    let recorder = Recorder::new(Box::new(OllamaClient::new(server.url())), &path).unwrap();
+    let cancel = CancellationToken::new();
+    assistant(Box::new(recorder))
+        .ask("What's this?", &cancel)
+        .await
+        .unwrap();
+
+    // The tool result differs from the recorded one
+    let replayer = Replayer::load(&path).unwrap();
+    let mut replayed = builder(Box::new(replayer))
+        .with_toolchain(Toolchain::new("src"))
+        .build();
+
+    match replayed.ask("What's this?", &cancel).await.unwrap_err() {
+        AssistantError::RequestFailed(OllamaError::ReplayError(error)) => {
+            assert_eq!(
+                error,
+                "the request differs from the recording at message 4 of 4 (recorded 4)"
+            )
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn recording_passes_tokens_on_as_they_arrive() {
+    let path = recording_path("stream");
+    let server = MockServer::start().await;
+    let chunk = |content: &str, done: bool| {
+        json!({
+            "model": "mock",
+            "message": { "role": "assistant", "content": content },
+            "done": done,
+        })
+    };
+    server.enqueue(
+        CHAT,
+        MockResponse::Chunks(vec![
+            chunk("It's ", false),
+            chunk("ollama", false),
+            chunk("_code", true),
+        ]),
+    );
+    let recorder = Recorder::new(Box::new(OllamaClient::new(server.url())), &path).unwrap();
+    let (handler, mut events) = events::channel();
+    let mut recorded = builder(Box::new(recorder))
+        .with_event_handler(handler)
+        .build();
+
+    let answer = recorded
+        .ask("What's this?", &CancellationToken::new())
+        .await
+        .unwrap();
+
+    assert_eq!(answer, "It's ollama_code");
+    let mut deltas = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let AssistantEvent::TokenDelta { content } = event {
+            deltas.push(content);
+        }
+    }
+    assert_eq!(deltas, ["It's ", "ollama", "_code"]);
+    let recording = std::fs::read_to_string(&path).unwrap();
+    let exchange: serde_json::Value = serde_json::from_str(recording.trim()).unwrap();
+    assert_eq!(
+        exchange["response"]["message"]["content"],
+        "It's ollama_code"
+    );
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,386 @@
+use std::{path::Path, time::Duration};
+
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use tokio::io::{
+    AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+};
+
+mod common;
+
+use common::{MockResponse, MockServer};
+use ollama_code::{
+    assistant::Assistant,
+    ollama::{OllamaClient, ToolDefinition},
+    server::{self, AssistantFactory},
+    tools::{Tool, ToolError, Toolchain},
+};
+
+const CHAT: &str = "/api/chat";
+const FIXTURE: &str = "evals/fixtures/calculator";
+
+/// Needs approval before it runs
+struct Deploy;
+
+impl Tool for Deploy {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::function(
+            "deploy",
+            "Deploys the project",
+            Some(json!({
+                "type": "object",
+                "properties": { "target": { "type": "string" } },
+                "required": ["target"],
+            })),
+        )
+    }
+
+    fn needs_approval(&self) -> bool {
+        true
+    }
+
+    fn call<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, ToolError>> {
+        Box::pin(async move { Ok(format!("Deployed to {}", arguments["target"])) })
+    }
+}
+
+/// The editor's side of the connection
+struct Client {
+    writer: WriteHalf<DuplexStream>,
+    lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    next_id: u64,
+    /// Notifications read while waiting for a response
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    async fn start(model: &MockServer) -> Self {
+        let url = model.url().to_string();
+        let factory: AssistantFactory = Box::new(move |workspace| {
+            Assistant::builder("mock")
+                .with_system_prompt("You are a test")
+                .with_backend(Box::new(OllamaClient::new(&url)))
+                .with_toolchain(Toolchain::new(workspace))
+                .with_tool(Deploy)
+        });
+        let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+        let (client_read, writer) = tokio::io::split(client_side);
+        let (server_read, server_write) = tokio::io::split(server_side);
+        tokio::spawn(async move {
+            server::serve(factory, Path::new(FIXTURE), server_read, server_write)
+                .await
+                .unwrap();
+        });
+
+        Self {
+            writer,
+            lines: BufReader::new(client_read).lines(),
+            next_id: 1,
+            notifications: Vec::new(),
+        }
+    }
+
+    async fn send_line(&mut self, line: &str) {
+        let line = format!("{}\n", line);
+        self.writer.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.send_line(&message.to_string()).await;
+        id
+    }
+
+    async fn receive(&mut self) -> Value {
+        let line = tokio::time::timeout(Duration::from_secs(5), self.lines.next_line())
+            .await
+            .expect("no message from the server")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn response(&mut self, id: u64) -> Value {
+        loop {
+            let message = self.receive().await;
+            if message["id"] == id {
+                return message;
+            }
+            assert!(message.get("id").is_none(), "unexpected {}", message);
+            self.notifications.push(message);
+        }
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> Value {
+        let id = self.request(method, params).await;
+        self.response(id).await
+    }
+
+    async fn notification(&mut self, method: &str) -> Value {
+        if let Some(index) = self
+            .notifications
+            .iter()
+            .position(|n| n["method"] == method)
+        {
+            return self.notifications.remove(index)["params"].clone();
+        }
+        loop {
+            let message = self.receive().await;
+            if message["method"] == method {
+                return message["params"].clone();
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    async fn create_session(&mut self) -> String {
+        let response = self.call("session/create", json!({})).await;
+        response["result"]["sessionId"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn event_types(&self) -> Vec<&str> {
+        self.notifications
+            .iter()
+            .filter(|n| n["method"] == "session/event")
+            .map(|n| n["params"]["event"]["type"].as_str().unwrap())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn answers_prompts_and_streams_events() {
+    let model = MockServer::start().await;
+    model
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("read_file", json!({ "path": "Cargo.toml" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("Version 0.3.1"));
+    let mut client = Client::start(&model).await;
+
+    let created = client.call("session/create", json!({})).await;
+    let session = created["result"]["sessionId"].as_str().unwrap().to_string();
+    assert_eq!(created["result"]["model"], "mock");
+    assert!(
+        created["result"]["workspace"]
+            .as_str()
+            .unwrap()
+            .ends_with(FIXTURE)
+    );
+
+    let response = client
+        .call(
+            "session/prompt",
+            json!({ "sessionId": session, "prompt": "Which version?" }),
+        )
+        .await;
+
+    assert_eq!(response["result"]["answer"], "Version 0.3.1");
+    assert_eq!(response["result"]["usage"]["tool_calls"], 1);
+    assert_eq!(
+        client.event_types(),
+        [
+            "turn_started",
+            "model_request",
+            "tool_call_started",
+            "tool_call_finished",
+            "model_request",
+            "token_delta",
+            "turn_finished"
+        ]
+    );
+    assert!(
+        client
+            .notifications
+            .iter()
+            .all(|n| n["params"]["sessionId"] == session.as_str())
+    );
+}
+
+#[tokio::test]
+async fn sessions_are_listed_and_closed() {
+    let model = MockServer::start().await;
+    let mut client = Client::start(&model).await;
+    let first = client.create_session().await;
+    let second = client.create_session().await;
+    assert_ne!(first, second);
+
+    let listed = client.call("session/list", json!({})).await;
+    let sessions = listed["result"]["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0]["sessionId"], first.as_str());
+    assert_eq!(sessions[0]["busy"], false);
+
+    let closed = client
+        .call("session/close", json!({ "sessionId": first }))
+        .await;
+    assert_eq!(closed["result"], json!({}));
+    let listed = client.call("session/list", json!({})).await;
+    assert_eq!(
+        listed["result"]["sessions"][0]["sessionId"],
+        second.as_str()
+    );
+    assert_eq!(listed["result"]["sessions"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn tool_calls_wait_for_approval() {
+    let model = MockServer::start().await;
+    model
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("deploy", json!({ "target": "staging" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("Deployed"))
+        .enqueue(
+            CHAT,
+            MockResponse::tool_call("deploy", json!({ "target": "production" })),
+        )
+        .enqueue(CHAT, MockResponse::answer("Not deployed"));
+    let mut client = Client::start(&model).await;
+    let session = client.create_session().await;
+
+    for (approved, expected) in [(true, "Deployed to \"staging\""), (false, "did not allow")] {
+        let prompt = client
+            .request(
+                "session/prompt",
+                json!({ "sessionId": session, "prompt": "Deploy" }),
+            )
+            .await;
+        let request = client.notification("session/approvalRequested").await;
+        assert_eq!(request["sessionId"], session.as_str());
+        assert_eq!(request["name"], "deploy");
+
+        let approve = client
+            .call(
+                "session/approve",
+                json!({ "sessionId": session, "callId": request["callId"], "approved": approved }),
+            )
+            .await;
+        assert_eq!(approve["result"], json!({}));
+        assert!(client.response(prompt).await.get("result").is_some());
+
+        let requests = model.requests(CHAT);
+        let last = requests.last().unwrap()["messages"]
+            .as_array()
+            .unwrap()
+            .last()
+            .unwrap()
+            .clone();
+        assert!(
+            last["content"].as_str().unwrap().contains(expected),
+            "{}",
+            last
+        );
+    }
+}
+
+#[tokio::test]
+async fn prompts_can_be_cancelled() {
+    let model = MockServer::start().await;
+    model
+        .enqueue(
+            CHAT,
+            MockResponse::Delayed(
+                Duration::from_secs(10),
+                Box::new(MockResponse::answer("Too late")),
+            ),
+        )
+        .enqueue(CHAT, MockResponse::answer("Hello again"));
+    let mut client = Client::start(&model).await;
+    let session = client.create_session().await;
+
+    let prompt = client
+        .request(
+            "session/prompt",
+            json!({ "sessionId": session, "prompt": "Hi" }),
+        )
+        .await;
+    client.notification("session/event").await;
+
+    let busy = client
+        .call(
+            "session/prompt",
+            json!({ "sessionId": session, "prompt": "Hi again" }),
+        )
+        .await;
+    assert_eq!(busy["error"]["code"], -32001);
+
+    let cancelled = client
+        .call("session/cancel", json!({ "sessionId": session }))
+        .await;
+    assert_eq!(cancelled["result"]["cancelled"], true);
+    let response = client.response(prompt).await;
+    assert_eq!(response["error"]["code"], -32800);
+
+    // The session is usable again right away
+    let response = client
+        .call(
+            "session/prompt",
+            json!({ "sessionId": session, "prompt": "Hi again" }),
+        )
+        .await;
+    assert_eq!(response["result"]["answer"], "Hello again");
+}
+
+#[tokio::test]
+async fn invalid_requests_get_errors() {
+    let model = MockServer::start().await;
+    model.enqueue(CHAT, MockResponse::error(400, "bad request"));
+    let mut client = Client::start(&model).await;
+
+    client.send_line("not json").await;
+    let response = client.receive().await;
+    assert_eq!(response["error"]["code"], -32700);
+    assert_eq!(response["id"], Value::Null);
+
+    let response = client.call("session/rename", json!({})).await;
+    assert_eq!(response["error"]["code"], -32601);
+
+    let response = client
+        .call(
+            "session/prompt",
+            json!({ "sessionId": "session-99", "prompt": "Hi" }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32602);
+    assert_eq!(response["error"]["message"], "Unknown session: session-99");
+
+    let response = client
+        .call("session/create", json!({ "workspace": "no/such/dir" }))
+        .await;
+    assert_eq!(response["error"]["code"], -32602);
+
+    let session = client.create_session().await;
+    let response = client
+        .call("session/prompt", json!({ "sessionId": session }))
+        .await;
+    assert_eq!(response["error"]["code"], -32602);
+
+    let response = client
+        .call(
+            "session/prompt",
+            json!({ "sessionId": session, "prompt": "Hi" }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32000);
+    assert!(
+        response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("bad request")
+    );
+
+    let response = client
+        .call(
+            "session/approve",
+            json!({ "sessionId": session, "callId": "call_1_0", "approved": true }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32602);
+}